@@ -0,0 +1,253 @@
+// Times drawing frames of dense meshes of tiny triangles, where setting up,
+// binning and handing out triangles costs more than covering their few
+// samples: closed meshes with cells a few samples across, and triangles
+// scattered all over the screen, which also stress the caches. They are
+// timed against filling the triangles one at a time like
+// fill_projected_triangles did before it was tiled. Timings depend on the
+// machine, so they are only printed:
+//
+//     cargo bench --bench dense_mesh
+
+// The benchmark only uses part of the modules it borrows from the program
+#![allow(dead_code, unused_imports)]
+
+#[path = "../src/color.rs"]
+mod color;
+#[path = "../src/matrix.rs"]
+mod matrix;
+#[path = "../src/screen_buffer.rs"]
+mod screen_buffer;
+#[path = "../src/shading.rs"]
+mod shading;
+#[path = "../src/world/mod.rs"]
+mod world;
+
+use color::Rgb;
+use screen_buffer::{AntiAliasing, ResolveFilter, ScreenBuffer};
+use std::time::{Duration, Instant};
+use world::projection::{ProjectedPoint, ProjectedTriangle};
+
+// Random numbers from a fixed seed, so that every run draws the same
+// triangles (xorshift64*)
+struct Random(u64);
+
+impl Random {
+    // A number in [min, max)
+    fn range(&mut self, min: f32, max: f32) -> f32 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        let unit = (self.0.wrapping_mul(0x2545f4914f6cdd1d) >> 40) as f32 / (1 << 24) as f32;
+        min + (max - min) * unit
+    }
+}
+
+// Overlapping triangles up to size across, at random depths, wound either
+// way, some of them partly off screen
+fn random_triangles(
+    count: usize,
+    size: f32,
+    width: usize,
+    height: usize,
+    random: &mut Random,
+) -> Vec<ProjectedTriangle> {
+    (0..count)
+        .map(|_| {
+            let x = random.range(-size, width as f32 + size);
+            let y = random.range(-size, height as f32 + size);
+            let mut vertex = || ProjectedPoint {
+                x: x + random.range(-size, size),
+                y: y + random.range(-size, size),
+                z: random.range(0.0, 1.0),
+                w: 1.0,
+            };
+            ProjectedTriangle {
+                v0: vertex(),
+                v1: vertex(),
+                v2: vertex(),
+            }
+        })
+        .collect()
+}
+
+// A closed mesh covering the screen and beyond it: a grid of cells split into
+// two triangles each, with the grid points moved at random
+fn grid_mesh(
+    width: usize,
+    height: usize,
+    cell: f32,
+    random: &mut Random,
+) -> Vec<ProjectedTriangle> {
+    let columns = (width as f32 / cell) as usize + 4;
+    let rows = (height as f32 / cell) as usize + 4;
+
+    let mut points = Vec::with_capacity((columns + 1) * (rows + 1));
+    for row in 0..=rows {
+        for column in 0..=columns {
+            let jitter = |random: &mut Random| random.range(-0.2, 0.2) * cell;
+            points.push(ProjectedPoint {
+                x: (column as f32 - 2.0) * cell + jitter(random),
+                y: (row as f32 - 2.0) * cell + jitter(random),
+                z: random.range(0.0, 1.0),
+                w: 1.0,
+            });
+        }
+    }
+
+    let mut triangles = Vec::with_capacity(columns * rows * 2);
+    for row in 0..rows {
+        for column in 0..columns {
+            let corner =
+                |dx: usize, dy: usize| points[(row + dy) * (columns + 1) + column + dx].clone();
+            triangles.push(ProjectedTriangle {
+                v0: corner(0, 0),
+                v1: corner(1, 0),
+                v2: corner(1, 1),
+            });
+            triangles.push(ProjectedTriangle {
+                v0: corner(0, 0),
+                v1: corner(1, 1),
+                v2: corner(0, 1),
+            });
+        }
+    }
+    triangles
+}
+
+// Fills triangles one at a time like fill_projected_triangles did before it
+// was tiled: snapped to 8 fractional bits of fixed point, testing every
+// sample in each bounding box with the top-left fill rule
+fn reference_fill(
+    buffer: &mut [Rgb],
+    z_buffer: &mut [f32],
+    width: usize,
+    height: usize,
+    triangles: &[ProjectedTriangle],
+    colors: &[Rgb],
+) {
+    const BITS: u32 = 8;
+    let to_fixed = |v: f32| (v * (1 << BITS) as f32).round() as i64;
+    let half = 1 << (BITS - 1);
+
+    // Edge function of p --> q, and its bias under the top-left fill rule
+    let edge = |p: (i64, i64), q: (i64, i64)| {
+        let (a, b) = (p.1 - q.1, q.0 - p.0);
+        let c = p.0 * q.1 - q.0 * p.1;
+        let bias = (a > 0 || (a == 0 && b > 0)) as i64 - 1;
+        move |x: i64, y: i64| a * x + b * y + c + bias
+    };
+
+    for (triangle, &color) in triangles.iter().zip(colors.iter()) {
+        let (v0, mut v1, mut v2) = (&triangle.v0, &triangle.v1, &triangle.v2);
+        let p0 = (to_fixed(v0.x), to_fixed(v0.y));
+        let mut p1 = (to_fixed(v1.x), to_fixed(v1.y));
+        let mut p2 = (to_fixed(v2.x), to_fixed(v2.y));
+
+        let mut area = (p1.0 - p0.0) * (p2.1 - p0.1) - (p2.0 - p0.0) * (p1.1 - p0.1);
+        if area == 0 {
+            continue;
+        }
+        if area < 0 {
+            std::mem::swap(&mut v1, &mut v2);
+            std::mem::swap(&mut p1, &mut p2);
+            area = -area;
+        }
+        let (e0, e1, e2) = (edge(p0, p1), edge(p1, p2), edge(p2, p0));
+
+        let min_x = p0.0.min(p1.0).min(p2.0) >> BITS;
+        let min_y = p0.1.min(p1.1).min(p2.1) >> BITS;
+        let max_x = p0.0.max(p1.0).max(p2.0) >> BITS;
+        let max_y = p0.1.max(p1.1).max(p2.1) >> BITS;
+        if max_x < 0 || max_y < 0 || min_x >= width as i64 || min_y >= height as i64 {
+            continue;
+        }
+        let area_recip = (area as f32).recip();
+
+        for y in min_y.max(0) as usize..=max_y.min(height as i64 - 1) as usize {
+            for x in min_x.max(0) as usize..=max_x.min(width as i64 - 1) as usize {
+                let sample_x = ((x as i64) << BITS) + half;
+                let sample_y = ((y as i64) << BITS) + half;
+                let (w2, w0, w1) = (
+                    e0(sample_x, sample_y),
+                    e1(sample_x, sample_y),
+                    e2(sample_x, sample_y),
+                );
+                if w0 >= 0 && w1 >= 0 && w2 >= 0 {
+                    let w0 = w0 as f32 * area_recip;
+                    let w1 = w1 as f32 * area_recip;
+                    let z = w0 * v0.z + w1 * v1.z + (1.0 - w0 - w1) * v2.z;
+                    let index = y * width + x;
+                    if z < z_buffer[index] {
+                        z_buffer[index] = z;
+                        buffer[index] = color;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn main() {
+    let (width, height) = (750, 750);
+    let frames = 7;
+
+    let meshes = [
+        (
+            "mesh of 3 sample cells",
+            grid_mesh(width, height, 3.0, &mut Random(2)),
+        ),
+        (
+            "mesh of 1.5 sample cells",
+            grid_mesh(width, height, 1.5, &mut Random(2)),
+        ),
+        (
+            "scattered 3 sample triangles",
+            random_triangles(200_000, 3.0, width, height, &mut Random(2)),
+        ),
+        (
+            "scattered 1 sample triangles",
+            random_triangles(1_000_000, 1.0, width, height, &mut Random(2)),
+        ),
+    ];
+
+    for (name, triangles) in &meshes {
+        let colors: Vec<Rgb> = (0..triangles.len()).map(|i| Rgb::gray(i as f32)).collect();
+
+        // Frames are drawn both ways in turn, so that both see the same load
+        // on the machine. Other work only ever makes a frame slower, so the
+        // fastest frame of each is the fairest to compare.
+        let mut buffer = vec![Rgb::BLACK; width * height];
+        let mut z_buffer = vec![0.0; width * height];
+        let mut screen =
+            ScreenBuffer::with_anti_aliasing(width, height, AntiAliasing::None, ResolveFilter::Box);
+
+        let (mut reference, mut tiled) = (Duration::MAX, Duration::MAX);
+        for _ in 0..frames {
+            buffer.fill(Rgb::BLACK);
+            z_buffer.fill(1.0);
+            let start = Instant::now();
+            reference_fill(
+                &mut buffer,
+                &mut z_buffer,
+                width,
+                height,
+                triangles,
+                &colors,
+            );
+            reference = reference.min(start.elapsed());
+
+            screen.clear(Rgb::BLACK);
+            let start = Instant::now();
+            screen.fill_projected_triangles(triangles, &colors);
+            tiled = tiled.min(start.elapsed());
+        }
+
+        println!(
+            "{}, {} triangles: {:?} against {:?} for the reference",
+            name,
+            triangles.len(),
+            tiled,
+            reference
+        );
+    }
+}
@@ -0,0 +1,216 @@
+use std::thread;
+
+use crate::screen_buffer::{view_depths, ScreenBuffer};
+use crate::world::camera::Camera;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AmbientOcclusion {
+    pub radius: f32,    // reach of occluders, in world units
+    pub samples: usize, // depth samples taken around each sample
+    pub strength: f32,  // how dark fully occluded samples get, in [0, 1]
+}
+
+impl Default for AmbientOcclusion {
+    fn default() -> Self {
+        AmbientOcclusion {
+            radius: 0.5,
+            samples: 16,
+            strength: 0.8,
+        }
+    }
+}
+
+// Occlusion is averaged over squares of this many samples on each side,
+// which is the size of the pattern the sample directions repeat in
+const BLUR_SIZE: usize = 4;
+// Neighbors further apart in depth than this fraction of the radius are not
+// blurred together, keeping occlusion from bleeding across silhouettes
+const BLUR_DEPTH_TOLERANCE: f32 = 0.5;
+
+// Darkens creases and cavities of a drawn frame using only its z buffer
+// (screen-space ambient occlusion).
+//
+// Depths are sampled in pairs on opposite sides of each sample, within the
+// radius projected onto the screen. A flat surface, however it is tilted,
+// has its sample halfway in depth between the pair, while a sample at the
+// bottom of a crease is behind the pair's average. How far behind gives the
+// occlusion, fading out for pairs much closer to the camera, which are more
+// likely to be separate objects in front than walls of a crease.
+//
+// The directions of the pairs are rotated from sample to sample, trading
+// banding for noise, which a depth-aware blur then smooths out.
+//
+// ref: https://en.wikipedia.org/wiki/Screen_space_ambient_occlusion
+pub fn apply_ambient_occlusion(
+    screen: &mut ScreenBuffer,
+    camera: &Camera,
+    options: &AmbientOcclusion,
+) {
+    let (width, height) = screen.raster_size();
+    let (buffer, z_buffer) = screen.samples_mut();
+
+    // Linear view space depth of every sample
+    let depths = view_depths(z_buffer, camera);
+    let depths = Depths {
+        depths: &depths,
+        width,
+        height,
+    };
+
+    let occlusion = for_each_row(width, height, |y, row| {
+        for (x, occlusion) in row.iter_mut().enumerate() {
+            *occlusion = sample_occlusion(camera, options, &depths, x, y);
+        }
+    });
+
+    let blurred = for_each_row(width, height, |y, row| {
+        for (x, blurred) in row.iter_mut().enumerate() {
+            *blurred = blur_occlusion(options, &depths, &occlusion, x, y);
+        }
+    });
+
+    for (color, occlusion) in buffer.iter_mut().zip(blurred.iter()) {
+        if *occlusion > 0.0 {
+            *color = *color * (1.0 - occlusion * options.strength);
+        }
+    }
+}
+
+// Fills a value for every sample, splitting the rows between threads
+fn for_each_row<F>(width: usize, height: usize, f: F) -> Vec<f32>
+where
+    F: Fn(usize, &mut [f32]) + Sync,
+{
+    let mut res = vec![0.0; width * height];
+    if width == 0 || height == 0 {
+        return res;
+    }
+
+    let threads = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    let rows_per_thread = height.div_ceil(threads);
+
+    thread::scope(|scope| {
+        for (i, chunk) in res.chunks_mut(rows_per_thread * width).enumerate() {
+            let f = &f;
+            scope.spawn(move || {
+                for (j, row) in chunk.chunks_mut(width).enumerate() {
+                    f(i * rows_per_thread + j, row);
+                }
+            });
+        }
+    });
+
+    res
+}
+
+// View space depths of a frame, infinite where nothing was drawn
+struct Depths<'a> {
+    depths: &'a [f32],
+    width: usize,
+    height: usize,
+}
+
+impl<'a> Depths<'a> {
+    // Depth at a sample, which is infinite off screen
+    fn at(&self, x: isize, y: isize) -> f32 {
+        if x < 0 || y < 0 || x >= self.width as isize || y >= self.height as isize {
+            f32::INFINITY
+        } else {
+            self.depths[y as usize * self.width + x as usize]
+        }
+    }
+}
+
+fn sample_occlusion(
+    camera: &Camera,
+    options: &AmbientOcclusion,
+    depths: &Depths,
+    x: usize,
+    y: usize,
+) -> f32 {
+    let depth = depths.at(x as isize, y as isize);
+    if depth.is_infinite() {
+        return 0.0;
+    }
+
+    // Radius on screen, in samples
+    let radius = camera.projected_size(options.radius, depth, depths.height);
+    if radius < 1.0 {
+        return 0.0;
+    }
+
+    let depth_at = |dx: f32, dy: f32| {
+        depths.at(
+            (x as f32 + dx).round() as isize,
+            (y as f32 + dy).round() as isize,
+        )
+    };
+
+    // Rotate the pattern by one of BLUR_SIZE x BLUR_SIZE angles, repeating
+    // over the screen so that the blur averages over all of them
+    let pattern = (y % BLUR_SIZE) * BLUR_SIZE + (x % BLUR_SIZE);
+    let rotation = pattern as f32 / (BLUR_SIZE * BLUR_SIZE) as f32 * std::f32::consts::PI;
+
+    let pairs = (options.samples / 2).max(1);
+    let mut total = 0.0;
+    for i in 0..pairs {
+        // Spiral outwards from the sample, spreading the pairs evenly
+        let angle = rotation + i as f32 * GOLDEN_ANGLE;
+        let distance = radius * ((i as f32 + 0.5) / pairs as f32).sqrt();
+        let dx = angle.cos() * distance;
+        let dy = angle.sin() * distance;
+
+        let a = depth_at(dx, dy);
+        let b = depth_at(-dx, -dy);
+        if a.is_infinite() || b.is_infinite() {
+            continue;
+        }
+
+        // Depth behind the pair's average, rising to full occlusion at the
+        // radius and falling off past it
+        let behind = depth - (a + b) / 2.0;
+        if behind > 0.0 {
+            total += (behind / options.radius).min(options.radius / behind);
+        }
+    }
+
+    (total / pairs as f32).min(1.0)
+}
+
+// Golden angle in radians, which spreads points on a spiral evenly
+const GOLDEN_ANGLE: f32 = 2.399_963;
+
+fn blur_occlusion(
+    options: &AmbientOcclusion,
+    depths: &Depths,
+    occlusion: &[f32],
+    x: usize,
+    y: usize,
+) -> f32 {
+    let width = depths.width;
+    let depth = depths.depths[y * width + x];
+    if depth.is_infinite() {
+        return 0.0;
+    }
+
+    let first_x = x.saturating_sub(BLUR_SIZE / 2);
+    let first_y = y.saturating_sub(BLUR_SIZE / 2);
+    let last_x = usize::min(first_x + BLUR_SIZE, width);
+    let last_y = usize::min(first_y + BLUR_SIZE, depths.height);
+
+    let mut total = 0.0;
+    let mut count = 0;
+    for sy in first_y..last_y {
+        for sx in first_x..last_x {
+            let index = sy * width + sx;
+            if (depths.depths[index] - depth).abs() <= options.radius * BLUR_DEPTH_TOLERANCE {
+                total += occlusion[index];
+                count += 1;
+            }
+        }
+    }
+
+    total / count as f32
+}
@@ -0,0 +1,597 @@
+use crate::ambient_occlusion::AmbientOcclusion;
+use crate::color::Rgb;
+use crate::fog::FogMode;
+use crate::hdr::{Bloom, ToneMapper, ToneMapping};
+use crate::lighting::LightingOptions;
+use crate::outline::Outline;
+use crate::post_process::{ColorCurves, EdgeDetect, Effect, Grayscale, Sharpen, Vignette};
+use crate::render::{BackFaces, LineCap, LineStyle, RenderMode, Transparency};
+use crate::screen_buffer::{AntiAliasing, ResolveFilter};
+use crate::shading::{Matcap, Shading, DEFAULT_TOON_BANDS};
+use crate::world::camera::{
+    Projection, DEFAULT_FAR, DEFAULT_FOV_Y, DEFAULT_NEAR, DEFAULT_VIEW_HEIGHT,
+};
+use crate::world::light::{Light, DEFAULT_SPOT_ANGLE};
+use crate::world::Point3;
+use std::path;
+use std::str::FromStr;
+
+pub struct Args {
+    pub file_name: String,
+    pub fps: u64,
+    pub scale: f32,
+
+    // camera lens, fov in degrees
+    pub projection: Projection,
+    pub fov: f32,
+    pub view_height: f32,
+    pub near: f32,
+    pub far: f32,
+
+    pub render_mode: RenderMode,
+    pub line_style: LineStyle,
+    pub shading: Shading,
+
+    // opacity in [0, 1], scaling any opacity of the object's faces
+    pub opacity: f32,
+    pub transparency: Transparency,
+    pub back_faces: BackFaces,
+    pub outline: Option<Outline>,
+
+    pub anti_aliasing: AntiAliasing,
+    pub aa_filter: ResolveFilter,
+
+    pub lights: Vec<Light>,
+    pub lighting: LightingOptions,
+
+    pub ambient_occlusion: Option<AmbientOcclusion>,
+    pub effects: Vec<Box<dyn Effect>>,
+
+    pub tone_mapping: ToneMapping,
+    pub bloom: Option<Bloom>,
+    pub hdr_output: Option<String>,
+
+    // fog color defaults to the background color
+    pub fog: Option<FogMode>,
+    pub fog_color: Option<Rgb>,
+}
+
+// Parses the command line:
+//
+//     shapes <file> [fps] [scale] [options]
+//
+// Options:
+//     --projection <perspective|orthographic>
+//     --fov <degrees>          vertical field of view for perspective projection
+//     --view-height <units>    visible height for orthographic projection
+//     --near <distance>
+//     --far <distance>
+//     --mode <solid|wireframe|hidden-line|overlay>
+//     --line-width <pixels>
+//     --line-cap <butt|round|square>
+//     --smooth-lines           anti-alias lines
+//     --shading <shading>      how the object's surfaces are shaded
+//     --opacity <opacity>      make the object see-through, from 0 to 1
+//     --transparency <sorted|weighted>
+//     --back-faces <cull|show|highlight>
+//     --outline                outline silhouettes and creases over the surfaces
+//     --crease-angle <degrees> bend between faces that is outlined, implies --outline
+//     --outline-color <rrggbb> hex color of outlines, implies --outline
+//     --ssaa <factor>          supersample each pixel on a factor x factor grid
+//     --msaa <factor>          multisample each pixel on a factor x factor grid
+//     --aa-filter <box|tent>
+//     --light <light>          add a light casting shadows, may be repeated
+//     --shadow-map-size <texels>
+//     --pcf-radius <texels>    shadow map texels filtered on each side for soft shadows
+//     --ground                 add a ground plane underneath the object
+//     --ssao                   darken creases with screen-space ambient occlusion
+//     --ssao-radius <units>    reach of ambient occlusion, implies --ssao
+//     --ssao-samples <count>   depth samples per pixel, implies --ssao
+//     --fog <fog>              fade surfaces and lines into fog with distance
+//     --fog-color <rrggbb>     hex color of the fog
+//     --effect <effect>        post-process each frame, may be repeated to apply in order
+//     --exposure <stops>       brighten or darken the frame before tone mapping
+//     --tonemap <clamp|reinhard|filmic>
+//     --bloom                  make pixels brighter than white glow
+//     --bloom-threshold <brightness> brightness past which pixels glow, implies --bloom
+//     --hdr-output <file>      also save the frame unclamped as a PFM image, with an fps of 0
+//
+// Lights are given as one of:
+//     directional:<dx>,<dy>,<dz>
+//     spot:<x>,<y>,<z>:<target x>,<target y>,<target z>[:<cone angle in degrees>]
+//
+// Effects are given as one of:
+//     grayscale
+//     curves:<input>,<output>[;<input>,<output>...]    levels in [0, 1]
+//     vignette[:<strength>]
+//     sharpen[:<amount>]
+//     edges
+//
+// Shading is given as one of:
+//     gray
+//     toon[:<bands>]           flat bands of light, best with --outline
+//     matcap:<file>            colors from a picture of a sphere, without lights
+//
+// Fog is given as one of:
+//     linear:<start distance>,<end distance>
+//     exp:<density>
+//     exp2:<density>
+//
+// Options may appear anywhere after the program name.
+pub fn parse(args: &[String]) -> Result<Args, String> {
+    let mut positional: Vec<&String> = Vec::new();
+    let mut res = Args {
+        file_name: String::new(),
+        fps: 30,
+        scale: 0.0,
+        projection: Projection::Perspective,
+        fov: DEFAULT_FOV_Y.to_degrees(),
+        view_height: DEFAULT_VIEW_HEIGHT,
+        near: DEFAULT_NEAR,
+        far: DEFAULT_FAR,
+        render_mode: RenderMode::Solid,
+        line_style: LineStyle::default(),
+        shading: Shading::Gray,
+        opacity: 1.0,
+        transparency: Transparency::Sorted,
+        back_faces: BackFaces::Cull,
+        outline: None,
+        anti_aliasing: AntiAliasing::None,
+        aa_filter: ResolveFilter::Box,
+        lights: Vec::new(),
+        lighting: LightingOptions::default(),
+        ambient_occlusion: None,
+        effects: Vec::new(),
+        tone_mapping: ToneMapping::default(),
+        bloom: None,
+        hdr_output: None,
+        fog: None,
+        fog_color: None,
+    };
+
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        if !arg.starts_with("--") {
+            positional.push(arg);
+            continue;
+        }
+
+        // Options without a value
+        if arg == "--smooth-lines" {
+            res.line_style.anti_aliased = true;
+            continue;
+        }
+        if arg == "--ground" {
+            res.lighting.ground_plane = true;
+            continue;
+        }
+        if arg == "--outline" {
+            res.outline.get_or_insert_with(Default::default);
+            continue;
+        }
+        if arg == "--ssao" {
+            res.ambient_occlusion.get_or_insert_with(Default::default);
+            continue;
+        }
+        if arg == "--bloom" {
+            res.bloom.get_or_insert_with(Default::default);
+            continue;
+        }
+
+        let value = match iter.next() {
+            Some(v) => v,
+            None => return Err(format!("missing value for {}", arg)),
+        };
+
+        match arg.as_str() {
+            "--projection" => res.projection = parse_projection(value)?,
+            "--fov" => res.fov = parse_value("fov", value)?,
+            "--view-height" => res.view_height = parse_value("view height", value)?,
+            "--near" => res.near = parse_value("near distance", value)?,
+            "--far" => res.far = parse_value("far distance", value)?,
+            "--mode" => res.render_mode = parse_render_mode(value)?,
+            "--line-width" => res.line_style.width = parse_value("line width", value)?,
+            "--line-cap" => res.line_style.cap = parse_line_cap(value)?,
+            "--shading" => res.shading = parse_shading(value)?,
+            "--opacity" => res.opacity = parse_value("opacity", value)?,
+            "--transparency" => res.transparency = parse_transparency(value)?,
+            "--back-faces" => res.back_faces = parse_back_faces(value)?,
+            "--crease-angle" => {
+                let angle: f32 = parse_value("crease angle", value)?;
+                if !(0.0..=180.0).contains(&angle) {
+                    return Err(format!(
+                        "crease angle must be between 0 and 180 degrees: {}",
+                        angle
+                    ));
+                }
+                res.outline
+                    .get_or_insert_with(Default::default)
+                    .crease_angle = angle.to_radians()
+            }
+            "--outline-color" => {
+                res.outline.get_or_insert_with(Default::default).color =
+                    parse_color("outline color", value)?
+            }
+            "--ssaa" => res.anti_aliasing = AntiAliasing::Supersample(parse_aa_factor(value)?),
+            "--msaa" => res.anti_aliasing = AntiAliasing::Multisample(parse_aa_factor(value)?),
+            "--aa-filter" => res.aa_filter = parse_resolve_filter(value)?,
+            "--light" => res.lights.push(parse_light(value)?),
+            "--shadow-map-size" => {
+                res.lighting.shadow_map_size = parse_value("shadow map size", value)?
+            }
+            "--pcf-radius" => res.lighting.pcf_radius = parse_value("pcf radius", value)?,
+            "--ssao-radius" => {
+                res.ambient_occlusion
+                    .get_or_insert_with(Default::default)
+                    .radius = parse_value("ssao radius", value)?
+            }
+            "--ssao-samples" => {
+                res.ambient_occlusion
+                    .get_or_insert_with(Default::default)
+                    .samples = parse_value("ssao samples", value)?
+            }
+            "--effect" => res.effects.push(parse_effect(value)?),
+            "--exposure" => res.tone_mapping.exposure = parse_value("exposure", value)?,
+            "--tonemap" => res.tone_mapping.tone_mapper = parse_tone_mapper(value)?,
+            "--bloom-threshold" => {
+                res.bloom.get_or_insert_with(Default::default).threshold =
+                    parse_value("bloom threshold", value)?
+            }
+            "--hdr-output" => res.hdr_output = Some(value.clone()),
+            "--fog" => res.fog = Some(parse_fog(value)?),
+            "--fog-color" => res.fog_color = Some(parse_color("fog color", value)?),
+            _ => return Err(format!("unknown option: {}", arg)),
+        }
+    }
+
+    if positional.is_empty() {
+        return Err("missing file argument".to_string());
+    }
+
+    res.file_name = positional[0].clone();
+    if !path::Path::new(&res.file_name).exists() {
+        return Err(format!("file \"{}\" not found", res.file_name));
+    }
+
+    if positional.len() >= 2 {
+        res.fps = parse_value("fps", positional[1])?;
+    }
+
+    if positional.len() >= 3 {
+        res.scale = parse_value("scale", positional[2])?;
+    }
+
+    if !(res.fov > 0.0 && res.fov < 180.0) {
+        return Err(format!("fov must be between 0 and 180 degrees: {}", res.fov));
+    }
+
+    if res.view_height <= 0.0 {
+        return Err(format!("view height must be positive: {}", res.view_height));
+    }
+
+    if res.line_style.width <= 0.0 {
+        return Err(format!(
+            "line width must be positive: {}",
+            res.line_style.width
+        ));
+    }
+
+    if !(0.0..=1.0).contains(&res.opacity) {
+        return Err(format!("opacity must be between 0 and 1: {}", res.opacity));
+    }
+
+    if !(16..=8192).contains(&res.lighting.shadow_map_size) {
+        return Err(format!(
+            "shadow map size must be between 16 and 8192: {}",
+            res.lighting.shadow_map_size
+        ));
+    }
+
+    if res.lighting.pcf_radius > 8 {
+        return Err(format!(
+            "pcf radius must be at most 8: {}",
+            res.lighting.pcf_radius
+        ));
+    }
+
+    if let Some(ambient_occlusion) = &res.ambient_occlusion {
+        if ambient_occlusion.radius <= 0.0 {
+            return Err(format!(
+                "ssao radius must be positive: {}",
+                ambient_occlusion.radius
+            ));
+        }
+
+        if !(1..=64).contains(&ambient_occlusion.samples) {
+            return Err(format!(
+                "ssao samples must be between 1 and 64: {}",
+                ambient_occlusion.samples
+            ));
+        }
+    }
+
+    if let Some(bloom) = &res.bloom {
+        if bloom.threshold < 0.0 {
+            return Err(format!(
+                "bloom threshold must not be negative: {}",
+                bloom.threshold
+            ));
+        }
+    }
+
+    if res.hdr_output.is_some() && res.fps != 0 {
+        return Err("--hdr-output requires an fps of 0".to_string());
+    }
+
+    if res.fog_color.is_some() && res.fog.is_none() {
+        return Err("--fog-color requires --fog".to_string());
+    }
+
+    if !(res.near > 0.0 && res.near < res.far) {
+        return Err(format!(
+            "invalid clipping distances: near {}, far {}",
+            res.near, res.far
+        ));
+    }
+
+    Ok(res)
+}
+
+fn parse_value<T: FromStr>(name: &str, value: &str) -> Result<T, String> {
+    match value.parse::<T>() {
+        Ok(val) => Ok(val),
+        Err(_) => Err(format!("invalid {}: {}", name, value)),
+    }
+}
+
+fn parse_projection(value: &str) -> Result<Projection, String> {
+    match value {
+        "perspective" => Ok(Projection::Perspective),
+        "orthographic" | "ortho" => Ok(Projection::Orthographic),
+        _ => Err(format!("invalid projection: {}", value)),
+    }
+}
+
+fn parse_render_mode(value: &str) -> Result<RenderMode, String> {
+    match value {
+        "solid" => Ok(RenderMode::Solid),
+        "wireframe" => Ok(RenderMode::Wireframe),
+        "hidden-line" => Ok(RenderMode::HiddenLine),
+        "overlay" => Ok(RenderMode::SolidWireframe),
+        _ => Err(format!("invalid render mode: {}", value)),
+    }
+}
+
+fn parse_line_cap(value: &str) -> Result<LineCap, String> {
+    match value {
+        "butt" => Ok(LineCap::Butt),
+        "round" => Ok(LineCap::Round),
+        "square" => Ok(LineCap::Square),
+        _ => Err(format!("invalid line cap: {}", value)),
+    }
+}
+
+fn parse_tone_mapper(value: &str) -> Result<ToneMapper, String> {
+    match value {
+        "clamp" => Ok(ToneMapper::Clamp),
+        "reinhard" => Ok(ToneMapper::Reinhard),
+        "filmic" | "aces" => Ok(ToneMapper::Filmic),
+        _ => Err(format!("invalid tone mapper: {}", value)),
+    }
+}
+
+fn parse_shading(value: &str) -> Result<Shading, String> {
+    let (name, params) = match value.split_once(':') {
+        Some((name, params)) => (name, Some(params)),
+        None => (value, None),
+    };
+
+    match (name, params) {
+        ("gray", None) => Ok(Shading::Gray),
+        ("toon", bands) => {
+            let bands = match bands {
+                Some(bands) => parse_value("toon bands", bands)?,
+                None => DEFAULT_TOON_BANDS,
+            };
+            if !(1..=16).contains(&bands) {
+                return Err(format!("toon bands must be between 1 and 16: {}", bands));
+            }
+            Ok(Shading::Toon { bands })
+        }
+        ("matcap", Some(file_name)) => match Matcap::load(file_name) {
+            Ok(matcap) => Ok(Shading::Matcap(matcap)),
+            Err(e) => Err(format!("failed to load matcap \"{}\": {}", file_name, e)),
+        },
+        _ => Err(format!("invalid shading: {}", value)),
+    }
+}
+
+fn parse_transparency(value: &str) -> Result<Transparency, String> {
+    match value {
+        "sorted" => Ok(Transparency::Sorted),
+        "weighted" => Ok(Transparency::Weighted),
+        _ => Err(format!("invalid transparency: {}", value)),
+    }
+}
+
+fn parse_back_faces(value: &str) -> Result<BackFaces, String> {
+    match value {
+        "cull" => Ok(BackFaces::Cull),
+        "show" => Ok(BackFaces::Show),
+        "highlight" => Ok(BackFaces::Highlight),
+        _ => Err(format!("invalid back faces: {}", value)),
+    }
+}
+
+fn parse_aa_factor(value: &str) -> Result<usize, String> {
+    let factor: usize = parse_value("anti-aliasing factor", value)?;
+    if (1..=8).contains(&factor) {
+        Ok(factor)
+    } else {
+        Err(format!(
+            "anti-aliasing factor must be between 1 and 8: {}",
+            factor
+        ))
+    }
+}
+
+fn parse_resolve_filter(value: &str) -> Result<ResolveFilter, String> {
+    match value {
+        "box" => Ok(ResolveFilter::Box),
+        "tent" => Ok(ResolveFilter::Tent),
+        _ => Err(format!("invalid anti-aliasing filter: {}", value)),
+    }
+}
+
+fn parse_point(name: &str, value: &str) -> Result<Point3, String> {
+    let coords: Vec<&str> = value.split(',').collect();
+    if coords.len() != 3 {
+        return Err(format!("invalid {}: {}", name, value));
+    }
+
+    Ok(Point3::new([
+        parse_value(name, coords[0])?,
+        parse_value(name, coords[1])?,
+        parse_value(name, coords[2])?,
+    ]))
+}
+
+fn parse_light(value: &str) -> Result<Light, String> {
+    let parts: Vec<&str> = value.split(':').collect();
+
+    match parts[0] {
+        "directional" if parts.len() == 2 => {
+            let direction = parse_point("light direction", parts[1])?;
+            if direction.magnitude() == 0.0 {
+                return Err(format!("light direction must not be zero: {}", value));
+            }
+            Ok(Light::directional(direction))
+        }
+        "spot" if parts.len() == 3 || parts.len() == 4 => {
+            let position = parse_point("light position", parts[1])?;
+            let target = parse_point("light target", parts[2])?;
+            if position == target {
+                return Err(format!(
+                    "light target must differ from its position: {}",
+                    value
+                ));
+            }
+
+            let angle: f32 = match parts.get(3) {
+                Some(angle) => parse_value("light cone angle", angle)?,
+                None => DEFAULT_SPOT_ANGLE.to_degrees(),
+            };
+            if !(angle > 0.0 && angle < 180.0) {
+                return Err(format!(
+                    "light cone angle must be between 0 and 180 degrees: {}",
+                    angle
+                ));
+            }
+
+            Ok(Light::spot(position, target, angle.to_radians()))
+        }
+        _ => Err(format!("invalid light: {}", value)),
+    }
+}
+
+// Colors are given in sRGB hex, like on the web
+fn parse_color(name: &str, value: &str) -> Result<Rgb, String> {
+    let hex = value.trim_start_matches('#');
+    match u32::from_str_radix(hex, 16) {
+        Ok(color) if hex.len() == 6 => Ok(Rgb::from_srgb(color)),
+        _ => Err(format!("invalid {}: {}", name, value)),
+    }
+}
+
+fn parse_effect(value: &str) -> Result<Box<dyn Effect>, String> {
+    let (name, params) = match value.split_once(':') {
+        Some((name, params)) => (name, Some(params)),
+        None => (value, None),
+    };
+
+    match (name, params) {
+        ("grayscale", None) => Ok(Box::new(Grayscale)),
+        ("curves", Some(params)) => {
+            let mut points: Vec<(f32, f32)> = Vec::new();
+            for point in params.split(';') {
+                let levels: Vec<&str> = point.split(',').collect();
+                if levels.len() != 2 {
+                    return Err(format!("invalid curve point: {}", point));
+                }
+
+                let input: f32 = parse_value("curve input level", levels[0])?;
+                let output: f32 = parse_value("curve output level", levels[1])?;
+                if !(0.0..=1.0).contains(&input) || !(0.0..=1.0).contains(&output) {
+                    return Err(format!("curve levels must be between 0 and 1: {}", point));
+                }
+                points.push((input, output));
+            }
+            Ok(Box::new(ColorCurves::new(&points)))
+        }
+        ("vignette", _) => {
+            let strength: f32 = match params {
+                Some(strength) => parse_value("vignette strength", strength)?,
+                None => 0.5,
+            };
+            if !(0.0..=1.0).contains(&strength) {
+                return Err(format!(
+                    "vignette strength must be between 0 and 1: {}",
+                    strength
+                ));
+            }
+            Ok(Box::new(Vignette {
+                strength,
+                radius: 0.5,
+            }))
+        }
+        ("sharpen", _) => {
+            let amount: f32 = match params {
+                Some(amount) => parse_value("sharpen amount", amount)?,
+                None => 1.0,
+            };
+            if amount < 0.0 {
+                return Err(format!("sharpen amount must not be negative: {}", amount));
+            }
+            Ok(Box::new(Sharpen { amount }))
+        }
+        ("edges", None) => Ok(Box::new(EdgeDetect)),
+        _ => Err(format!("invalid effect: {}", value)),
+    }
+}
+
+fn parse_fog(value: &str) -> Result<FogMode, String> {
+    let parts: Vec<&str> = value.split(':').collect();
+
+    match parts[..] {
+        ["linear", range] => {
+            let distances: Vec<&str> = range.split(',').collect();
+            if distances.len() != 2 {
+                return Err(format!("invalid fog: {}", value));
+            }
+
+            let start: f32 = parse_value("fog start", distances[0])?;
+            let end: f32 = parse_value("fog end", distances[1])?;
+            if !(start >= 0.0 && start < end) {
+                return Err(format!(
+                    "fog must start before it ends: start {}, end {}",
+                    start, end
+                ));
+            }
+
+            Ok(FogMode::Linear { start, end })
+        }
+        ["exp", density] | ["exp2", density] => {
+            let density: f32 = parse_value("fog density", density)?;
+            if density <= 0.0 {
+                return Err(format!("fog density must be positive: {}", density));
+            }
+
+            if parts[0] == "exp" {
+                Ok(FogMode::Exponential { density })
+            } else {
+                Ok(FogMode::ExponentialSquared { density })
+            }
+        }
+        _ => Err(format!("invalid fog: {}", value)),
+    }
+}
@@ -0,0 +1,199 @@
+use std::ops::{Add, AddAssign, Mul, Sub};
+
+// A color in linear light, where channels are proportional to the amount of
+// light, so that colors can be added, scaled and mixed like light is.
+// Displayable colors have channels in [0, 1].
+//
+// Colors written as 0xRRGGBB are in sRGB, which spends more of its levels on
+// dark colors, and are only converted to and from it at the edges: when read
+// from options and when written to the window or an image.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Rgb {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+}
+
+// A color in linear light with an opacity in [0, 1], not premultiplied
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Rgba {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+impl Rgb {
+    pub const BLACK: Rgb = Rgb::new(0.0, 0.0, 0.0);
+
+    pub const fn new(r: f32, g: f32, b: f32) -> Rgb {
+        Rgb { r, g, b }
+    }
+
+    pub const fn gray(value: f32) -> Rgb {
+        Rgb::new(value, value, value)
+    }
+
+    // Decodes a 0xRRGGBB color in sRGB
+    pub fn from_srgb(color: u32) -> Rgb {
+        let channel = |shift: u32| srgb_to_linear(((color >> shift) & 0xff) as f32 / 255.0);
+        Rgb::new(channel(16), channel(8), channel(0))
+    }
+
+    // Encodes the color as 0xRRGGBB in sRGB, clamping channels to [0, 1]
+    pub fn to_srgb(self) -> u32 {
+        let channel = |c: f32| (linear_to_srgb(c.clamp(0.0, 1.0)) * 255.0).round() as u32;
+        (channel(self.r) << 16) | (channel(self.g) << 8) | channel(self.b)
+    }
+
+    // Mixes towards other by t in [0, 1]
+    pub fn lerp(self, other: Rgb, t: f32) -> Rgb {
+        self + (other - self) * t
+    }
+
+    // Relative luminance, the brightness of the color as seen by the eye
+    //
+    // ref: https://en.wikipedia.org/wiki/Relative_luminance
+    pub fn luminance(self) -> f32 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    pub fn map<F: Fn(f32) -> f32>(self, f: F) -> Rgb {
+        Rgb::new(f(self.r), f(self.g), f(self.b))
+    }
+
+    pub fn with_alpha(self, a: f32) -> Rgba {
+        Rgba {
+            r: self.r,
+            g: self.g,
+            b: self.b,
+            a,
+        }
+    }
+}
+
+impl Rgba {
+    pub fn rgb(self) -> Rgb {
+        Rgb::new(self.r, self.g, self.b)
+    }
+
+    // Blends the color over dst by its opacity
+    pub fn over(self, dst: Rgb) -> Rgb {
+        dst.lerp(self.rgb(), self.a)
+    }
+}
+
+impl Add for Rgb {
+    type Output = Rgb;
+
+    fn add(self, other: Rgb) -> Rgb {
+        Rgb::new(self.r + other.r, self.g + other.g, self.b + other.b)
+    }
+}
+
+impl AddAssign for Rgb {
+    fn add_assign(&mut self, other: Rgb) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for Rgb {
+    type Output = Rgb;
+
+    fn sub(self, other: Rgb) -> Rgb {
+        Rgb::new(self.r - other.r, self.g - other.g, self.b - other.b)
+    }
+}
+
+impl Mul<f32> for Rgb {
+    type Output = Rgb;
+
+    fn mul(self, k: f32) -> Rgb {
+        Rgb::new(self.r * k, self.g * k, self.b * k)
+    }
+}
+
+// Channel by channel, like light reflecting off of a colored surface
+impl Mul for Rgb {
+    type Output = Rgb;
+
+    fn mul(self, other: Rgb) -> Rgb {
+        Rgb::new(self.r * other.r, self.g * other.g, self.b * other.b)
+    }
+}
+
+// The sRGB transfer functions, between an encoded sRGB level and linear light,
+// both in [0, 1]
+//
+// ref: https://en.wikipedia.org/wiki/SRGB#Transformation
+pub fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-6, "{} is not {}", a, b);
+    }
+
+    #[test]
+    fn transfer_functions_keep_black_and_white() {
+        assert_eq!(srgb_to_linear(0.0), 0.0);
+        assert_eq!(linear_to_srgb(0.0), 0.0);
+        assert_near(srgb_to_linear(1.0), 1.0);
+        assert_near(linear_to_srgb(1.0), 1.0);
+    }
+
+    #[test]
+    fn transfer_functions_are_linear_below_their_cutoffs() {
+        assert_eq!(srgb_to_linear(0.04045), 0.04045 / 12.92);
+        assert_eq!(linear_to_srgb(0.0031308), 0.0031308 * 12.92);
+
+        // The curves continue the lines past the cutoffs without a jump
+        assert_near(srgb_to_linear(0.04046), 0.04046 / 12.92);
+        assert_near(linear_to_srgb(0.0031309), 0.0031309 * 12.92);
+        assert!(srgb_to_linear(0.04046) > srgb_to_linear(0.04045));
+        assert!(linear_to_srgb(0.0031309) > linear_to_srgb(0.0031308));
+
+        // Mid gray in sRGB is about a fifth of the light of white
+        assert!((srgb_to_linear(0.5) - 0.214).abs() < 1e-3);
+    }
+
+    #[test]
+    fn transfer_functions_invert_each_other() {
+        for i in 0..=1000 {
+            let v = i as f32 / 1000.0;
+            assert!((linear_to_srgb(srgb_to_linear(v)) - v).abs() < 1e-5);
+            assert!((srgb_to_linear(linear_to_srgb(v)) - v).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn srgb_colors_round_trip() {
+        for level in 0..=255 {
+            let color = (level << 16) | ((255 - level) << 8) | (level * 7 % 256);
+            assert_eq!(Rgb::from_srgb(color).to_srgb(), color);
+        }
+    }
+
+    #[test]
+    fn encoding_clamps_channels() {
+        assert_eq!(Rgb::new(2.0, -1.0, 1.0).to_srgb(), 0xff00ff);
+        assert_eq!(Rgb::gray(100.0).to_srgb(), 0xffffff);
+        assert_eq!(Rgb::gray(-0.5).to_srgb(), 0x000000);
+    }
+}
@@ -0,0 +1,116 @@
+use crate::color::Rgb;
+use crate::world::camera::Camera;
+
+// How fog thickens with distance from the camera
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FogMode {
+    Linear { start: f32, end: f32 }, // clear before start, opaque past end
+    Exponential { density: f32 },
+    ExponentialSquared { density: f32 }, // clearer up close, thicker further out
+}
+
+// Fog that fades whatever is drawn towards its color with view depth
+//
+// ref: https://en.wikipedia.org/wiki/Distance_fog
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Fog {
+    pub mode: FogMode,
+    pub color: Rgb,
+}
+
+impl Fog {
+    // Fraction of a color hidden by fog at a view depth, in [0, 1]
+    pub fn amount(&self, depth: f32) -> f32 {
+        let clear = match self.mode {
+            FogMode::Linear { start, end } => (end - depth) / (end - start),
+            FogMode::Exponential { density } => (-density * depth).exp(),
+            FogMode::ExponentialSquared { density } => (-(density * depth).powi(2)).exp(),
+        };
+        1.0 - clear.clamp(0.0, 1.0)
+    }
+
+    // Fades a color drawn at a view depth into the fog
+    pub fn apply(&self, color: Rgb, depth: f32) -> Rgb {
+        let amount = self.amount(depth);
+        if amount > 0.0 {
+            color.lerp(self.color, amount)
+        } else {
+            color
+        }
+    }
+}
+
+// Fog as seen through a camera, for fading lines and points that only know
+// their normalized depth (depth cueing)
+pub struct DepthCue<'a> {
+    fog: Fog,
+    camera: &'a Camera,
+}
+
+impl<'a> DepthCue<'a> {
+    pub fn new(fog: Fog, camera: &'a Camera) -> DepthCue<'a> {
+        DepthCue { fog, camera }
+    }
+
+    // Fades a color drawn at normalized depth z into the fog
+    pub fn apply(&self, color: Rgb, z: f32) -> Rgb {
+        self.fog.apply(color, self.camera.view_depth(z))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fog(mode: FogMode) -> Fog {
+        Fog {
+            mode,
+            color: Rgb::gray(1.0),
+        }
+    }
+
+    fn assert_near(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-6, "{} is not {}", a, b);
+    }
+
+    #[test]
+    fn linear_fog_ramps_between_start_and_end() {
+        let fog = fog(FogMode::Linear {
+            start: 2.0,
+            end: 6.0,
+        });
+
+        assert_eq!(fog.amount(0.0), 0.0);
+        assert_eq!(fog.amount(2.0), 0.0);
+        assert_near(fog.amount(3.0), 0.25);
+        assert_near(fog.amount(4.0), 0.5);
+        assert_eq!(fog.amount(6.0), 1.0);
+        assert_eq!(fog.amount(100.0), 1.0);
+    }
+
+    #[test]
+    fn exponential_fog_thickens_with_depth() {
+        let fog = fog(FogMode::Exponential { density: 0.5 });
+
+        assert_eq!(fog.amount(0.0), 0.0);
+        assert_near(fog.amount(2.0), 1.0 - (-1.0f32).exp());
+        assert_near(fog.amount(4.0), 1.0 - (-2.0f32).exp());
+        assert!(fog.amount(1e3) > 0.999);
+    }
+
+    #[test]
+    fn exponential_squared_fog_is_clearer_up_close() {
+        let squared = fog(FogMode::ExponentialSquared { density: 0.5 });
+        let exponential = fog(FogMode::Exponential { density: 0.5 });
+
+        assert_eq!(squared.amount(0.0), 0.0);
+        assert_near(squared.amount(1.0), 1.0 - (-0.25f32).exp());
+        assert_near(squared.amount(2.0), 1.0 - (-1.0f32).exp());
+        assert_near(squared.amount(4.0), 1.0 - (-4.0f32).exp());
+
+        // Both hide the same amount at 1 / density, with less hidden before
+        // it and more after it by squared fog
+        assert!(squared.amount(1.0) < exponential.amount(1.0));
+        assert!(squared.amount(4.0) > exponential.amount(4.0));
+    }
+}
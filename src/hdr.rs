@@ -0,0 +1,259 @@
+use crate::color::Rgb;
+use crate::screen_buffer::ScreenBuffer;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+// How colors brighter than white are brought into the displayable range
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ToneMapper {
+    Clamp,    // cut off at white, like an 8-bit buffer would
+    Reinhard, // compress every channel by c / (1 + c), never quite reaching white
+    Filmic,   // an S-curve fitted to the ACES film look, with a soft shoulder
+}
+
+// Maps the light of a frame, which may be brighter than white, to colors
+// that can be shown. Exposure is in stops, each doubling the light.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ToneMapping {
+    pub exposure: f32,
+    pub tone_mapper: ToneMapper,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        ToneMapping {
+            exposure: 0.0,
+            tone_mapper: ToneMapper::Clamp,
+        }
+    }
+}
+
+impl ToneMapping {
+    pub fn apply(&self, color: Rgb) -> Rgb {
+        let color = color * self.exposure.exp2();
+        match self.tone_mapper {
+            ToneMapper::Clamp => color.map(|c| c.clamp(0.0, 1.0)),
+            ToneMapper::Reinhard => color.map(|c| c.max(0.0) / (1.0 + c.max(0.0))),
+            ToneMapper::Filmic => color.map(aces_filmic),
+        }
+    }
+}
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve
+//
+// ref: https://knarkowicz.wordpress.com/2016/01/06/aces-filmic-tone-mapping-curve/
+fn aces_filmic(c: f32) -> f32 {
+    let c = c.max(0.0) * 0.6; // the fit is made for ACES's exposure, a little lower
+    ((c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14)).clamp(0.0, 1.0)
+}
+
+// Glow around pixels brighter than the threshold, like bright light scattering
+// in a lens. The light past the threshold is blurred over about radius pixels
+// and added back, weighted by strength.
+//
+// ref: https://en.wikipedia.org/wiki/Bloom_(shader_effect)
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Bloom {
+    pub threshold: f32,
+    pub strength: f32,
+    pub radius: f32,
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Bloom {
+            threshold: 1.0,
+            strength: 0.5,
+            radius: 12.0,
+        }
+    }
+}
+
+// Adds bloom to a drawn and resolved frame, before tone mapping
+pub fn apply_bloom(screen: &mut ScreenBuffer, bloom: &Bloom) {
+    let (width, height) = screen.size();
+
+    // Keep only the light past the threshold, scaling the whole color so that
+    // glows keep the hue of what makes them
+    let bright: Vec<Rgb> = screen
+        .pixels()
+        .iter()
+        .map(|&color| {
+            let luminance = color.luminance();
+            if luminance > bloom.threshold {
+                color * ((luminance - bloom.threshold) / luminance)
+            } else {
+                Rgb::BLACK
+            }
+        })
+        .collect();
+
+    if bright.iter().all(|&color| color == Rgb::BLACK) {
+        return;
+    }
+
+    // A Gaussian blur reaching 3 standard deviations out to the radius,
+    // which separates into a pass along rows and one along columns
+    let sigma = (bloom.radius / 3.0).max(0.5);
+    let reach = bloom.radius.ceil() as isize;
+    let kernel: Vec<f32> = (-reach..=reach)
+        .map(|i| (-(i * i) as f32 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let total: f32 = kernel.iter().sum();
+    let kernel: Vec<f32> = kernel.iter().map(|k| k / total).collect();
+
+    let blur = |colors: &[Rgb], step: (isize, isize)| -> Vec<Rgb> {
+        let mut blurred = vec![Rgb::BLACK; colors.len()];
+        for y in 0..height as isize {
+            for x in 0..width as isize {
+                let mut sum = Rgb::BLACK;
+                for (i, k) in (-reach..=reach).zip(kernel.iter()) {
+                    let sx = (x + i * step.0).clamp(0, width as isize - 1);
+                    let sy = (y + i * step.1).clamp(0, height as isize - 1);
+                    sum += colors[sy as usize * width + sx as usize] * *k;
+                }
+                blurred[y as usize * width + x as usize] = sum;
+            }
+        }
+        blurred
+    };
+    let glow = blur(&blur(&bright, (1, 0)), (0, 1));
+
+    for (color, glow) in screen.pixels_mut().iter_mut().zip(glow.iter()) {
+        *color += *glow * bloom.strength;
+    }
+}
+
+// Brings a resolved frame into the displayable range, after which its colors
+// are in [0, 1] and ready for display-referred effects and encoding
+pub fn apply_tone_mapping(screen: &mut ScreenBuffer, tone_mapping: &ToneMapping) {
+    for color in screen.pixels_mut() {
+        *color = tone_mapping.apply(*color);
+    }
+}
+
+// Saves colors in linear light, one per pixel row by row, as a Portable
+// Float Map, keeping colors brighter than white for compositing elsewhere
+//
+// ref: https://www.pauldebevec.com/Research/HDR/PFM/
+pub fn write_pfm(path: &str, colors: &[Rgb], width: usize, height: usize) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    encode_pfm(&mut file, colors, width, height)?;
+    file.flush()
+}
+
+fn encode_pfm<W: Write>(
+    out: &mut W,
+    colors: &[Rgb],
+    width: usize,
+    height: usize,
+) -> io::Result<()> {
+    // A negative scale marks little-endian floats
+    write!(out, "PF\n{} {}\n-1.0\n", width, height)?;
+
+    // Rows are stored from the bottom up
+    for row in colors.chunks(width).rev() {
+        for color in row {
+            for c in [color.r, color.g, color.b] {
+                out.write_all(&c.to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-6, "{} is not {}", a, b);
+    }
+
+    fn tone_mapping(exposure: f32, tone_mapper: ToneMapper) -> ToneMapping {
+        ToneMapping {
+            exposure,
+            tone_mapper,
+        }
+    }
+
+    #[test]
+    fn clamp_cuts_off_at_black_and_white() {
+        let clamp = tone_mapping(0.0, ToneMapper::Clamp);
+
+        assert_eq!(
+            clamp.apply(Rgb::new(-1.0, 0.25, 4.0)),
+            Rgb::new(0.0, 0.25, 1.0)
+        );
+        assert_eq!(clamp.apply(Rgb::gray(1.0)), Rgb::gray(1.0));
+    }
+
+    #[test]
+    fn reinhard_compresses_towards_white() {
+        let reinhard = tone_mapping(0.0, ToneMapper::Reinhard);
+
+        assert_eq!(reinhard.apply(Rgb::BLACK), Rgb::BLACK);
+        assert_eq!(reinhard.apply(Rgb::gray(1.0)), Rgb::gray(0.5));
+        assert_eq!(reinhard.apply(Rgb::gray(3.0)), Rgb::gray(0.75));
+        assert_eq!(reinhard.apply(Rgb::gray(-1.0)), Rgb::BLACK);
+        assert!(reinhard.apply(Rgb::gray(1e6)).r < 1.0);
+    }
+
+    #[test]
+    fn filmic_rolls_off_into_white() {
+        let filmic = tone_mapping(0.0, ToneMapper::Filmic);
+
+        assert_eq!(filmic.apply(Rgb::BLACK), Rgb::BLACK);
+        assert_eq!(filmic.apply(Rgb::gray(-1.0)), Rgb::BLACK);
+        // White goes in as 0.6 after the exposure adjustment
+        assert_near(aces_filmic(1.0), 0.9216 / 1.3688);
+        assert_eq!(aces_filmic(100.0), 1.0);
+
+        // The curve rises all the way, steepest in the mid tones
+        let levels: Vec<f32> = (0..=40).map(|i| aces_filmic(i as f32 / 10.0)).collect();
+        assert!(levels.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(levels[6] - levels[5] > levels[36] - levels[35]);
+    }
+
+    #[test]
+    fn exposure_scales_light_by_stops() {
+        let color = Rgb::new(0.1, 0.2, 0.4);
+
+        assert_eq!(
+            tone_mapping(1.0, ToneMapper::Clamp).apply(color),
+            Rgb::new(0.2, 0.4, 0.8)
+        );
+        assert_eq!(
+            tone_mapping(-1.0, ToneMapper::Clamp).apply(color),
+            Rgb::new(0.05, 0.1, 0.2)
+        );
+        assert_eq!(
+            tone_mapping(2.0, ToneMapper::Reinhard).apply(Rgb::gray(0.25)),
+            Rgb::gray(0.5)
+        );
+    }
+
+    #[test]
+    fn pfm_stores_rows_bottom_up_in_little_endian() {
+        let colors = [
+            Rgb::new(1.0, 2.0, 3.0),
+            Rgb::new(4.0, 5.0, 6.0),
+            Rgb::new(-1.0, 0.5, 1e6),
+            Rgb::new(0.0, 0.25, 8.0),
+        ];
+        let mut out = Vec::new();
+        encode_pfm(&mut out, &colors, 2, 2).unwrap();
+
+        let header = b"PF\n2 2\n-1.0\n";
+        assert_eq!(&out[..header.len()], header);
+
+        let floats: Vec<f32> = out[header.len()..]
+            .chunks(4)
+            .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .collect();
+        assert_eq!(
+            floats,
+            [-1.0, 0.5, 1e6, 0.0, 0.25, 8.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0]
+        );
+    }
+}
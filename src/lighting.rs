@@ -0,0 +1,301 @@
+use crate::color::Rgb;
+use crate::render::{
+    render_surfaces, BackFaces, ObjectOrientation, RenderOptions, TransformedObject,
+};
+use crate::screen_buffer::{ScreenBuffer, FAR_DEPTH};
+use crate::world::camera::Camera;
+use crate::world::light::{Light, LightKind};
+use crate::world::projection::clip_to_screen;
+use crate::world::{Object, Point3};
+
+// Light reaching every surface, lit or not
+const AMBIENT_LIGHT: f32 = 0.1;
+// Light shining from the camera, so that shapes read even in shadow
+const HEADLIGHT: f32 = 0.3;
+
+// Samples closer to the shadow map than this are not in shadow, which keeps
+// surfaces from shadowing themselves due to limited depth precision
+const SHADOW_DEPTH_BIAS: f32 = 1e-3;
+// Positions are moved off of their surface by this many shadow map texels
+// before testing, for the same reason
+const SHADOW_NORMAL_OFFSET: f32 = 1.5;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LightingOptions {
+    pub shadow_map_size: usize, // width and height of each shadow map
+    pub pcf_radius: usize,      // shadow map texels filtered on each side of a sample
+    pub ground_plane: bool,
+}
+
+impl Default for LightingOptions {
+    fn default() -> Self {
+        LightingOptions {
+            shadow_map_size: 1024,
+            pcf_radius: 1,
+            ground_plane: false,
+        }
+    }
+}
+
+// Depth of the scene as seen from a light. A point is in shadow when
+// something is closer to the light than it is.
+//
+// ref: https://en.wikipedia.org/wiki/Shadow_mapping
+pub struct ShadowMap {
+    depth: ScreenBuffer,
+    camera: Camera,
+    size: usize,
+    texel_size: f32, // size of a texel in world units, around the shadow casters
+    pcf_radius: usize,
+}
+
+impl ShadowMap {
+    // Renders the depth of an object, which must be inside of the sphere
+    // around center, from the light's point of view
+    pub fn render(
+        light: &Light,
+        object: &Object,
+        orientation: ObjectOrientation,
+        center: Point3,
+        radius: f32,
+        options: &LightingOptions,
+    ) -> ShadowMap {
+        let size = options.shadow_map_size;
+        let camera = light.shadow_camera(center, radius);
+
+        let mut depth = ScreenBuffer::new(size, size);
+        depth.clear(Rgb::BLACK);
+        let transformed = TransformedObject::new(object, &camera, orientation);
+
+        // Faces cast shadows whichever way they face the light, so that open
+        // surfaces and faces culled from the camera still block it
+        let render_options = RenderOptions {
+            back_faces: BackFaces::Show,
+            ..RenderOptions::default()
+        };
+        render_surfaces(
+            object,
+            &transformed,
+            &mut depth,
+            &camera,
+            true,
+            None,
+            &render_options,
+        );
+
+        let texel_size = match light.kind {
+            LightKind::Directional { .. } => 2.0 * radius / size as f32,
+            LightKind::Spot {
+                position, angle, ..
+            } => 2.0 * (angle / 2.0).tan() * (center - position).magnitude() / size as f32,
+        };
+
+        ShadowMap {
+            depth,
+            camera,
+            size,
+            texel_size,
+            pcf_radius: options.pcf_radius,
+        }
+    }
+
+    // Fraction of the light reaching p, in [0, 1]. Depth comparisons are
+    // made over a square of texels around p and averaged, which softens the
+    // edges of shadows (percentage-closer filtering).
+    pub fn visibility(&self, p: Point3) -> f32 {
+        let clip = self.camera.project_to_clip(p);
+
+        // Points behind the light are never shadowed
+        if clip[3] <= 0.0 || clip[2] < 0.0 {
+            return 1.0;
+        }
+
+        let projected = clip_to_screen(clip, (self.size, self.size));
+        let (x, y) = projected.pixel();
+        let radius = self.pcf_radius as isize;
+
+        let mut lit = 0;
+        let mut taps = 0;
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                taps += 1;
+
+                // Texels outside of the map, or that nothing was drawn to,
+                // have no shadow casters
+                match self.depth.get_depth((x + dx, y + dy)) {
+                    Some(d) if d < FAR_DEPTH && projected.z > d + SHADOW_DEPTH_BIAS => {}
+                    _ => lit += 1,
+                }
+            }
+        }
+
+        lit as f32 / taps as f32
+    }
+}
+
+// Lights of a scene and their shadow maps, ready for shading a frame
+pub struct Lighting<'a> {
+    lights: &'a [Light],
+    shadow_maps: Vec<ShadowMap>,
+}
+
+impl<'a> Lighting<'a> {
+    // Renders a shadow map for each light. Only the object casts shadows,
+    // and it must be inside of the sphere around center.
+    pub fn new(
+        lights: &'a [Light],
+        object: &Object,
+        orientation: ObjectOrientation,
+        center: Point3,
+        radius: f32,
+        options: &LightingOptions,
+    ) -> Lighting<'a> {
+        let shadow_maps = lights
+            .iter()
+            .map(|light| ShadowMap::render(light, object, orientation, center, radius, options))
+            .collect();
+
+        Lighting {
+            lights,
+            shadow_maps,
+        }
+    }
+
+    // Brightness of a surface at p with unit normal n, which is over 1 where
+    // lights add up to more than white. facing is how directly the surface
+    // faces the camera, in [0, 1].
+    pub fn intensity(&self, p: Point3, n: Point3, facing: f32) -> f32 {
+        let mut total = AMBIENT_LIGHT + HEADLIGHT * facing;
+
+        for (light, shadow_map) in self.lights.iter().zip(self.shadow_maps.iter()) {
+            // Lambertian reflection; surfaces facing away from the light are
+            // not lit by it, and can skip the shadow test
+            let diffuse = n.dot(light.direction_to_light(p));
+            if diffuse <= 0.0 {
+                continue;
+            }
+
+            let cone = light.cone_factor(p);
+            if cone <= 0.0 {
+                continue;
+            }
+
+            let offset = n * (shadow_map.texel_size * SHADOW_NORMAL_OFFSET);
+            let visibility = shadow_map.visibility(p + offset);
+            total += light.intensity * diffuse * cone * visibility;
+        }
+
+        total
+    }
+
+    // How closely a surface at p with unit normal n reflects a light towards
+    // the viewer, as the cosine of the angle between n and the halfway vector
+    // between the directions to the light and to the viewer (Blinn-Phong).
+    // The closest reflection of any light counts, dimmed where it is shadowed.
+    //
+    // ref: https://en.wikipedia.org/wiki/Blinn%E2%80%93Phong_reflection_model
+    pub fn specular(&self, p: Point3, n: Point3, to_viewer: Point3) -> f32 {
+        let mut closest: f32 = 0.0;
+
+        for (light, shadow_map) in self.lights.iter().zip(self.shadow_maps.iter()) {
+            let to_light = light.direction_to_light(p);
+            if n.dot(to_light) <= 0.0 || light.cone_factor(p) <= 0.0 {
+                continue;
+            }
+
+            let reflection = n.dot((to_light + to_viewer).normalize());
+            if reflection <= closest {
+                continue;
+            }
+
+            let offset = n * (shadow_map.texel_size * SHADOW_NORMAL_OFFSET);
+            closest = closest.max(reflection * shadow_map.visibility(p + offset));
+        }
+
+        closest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::projection::{ProjectedPoint, ProjectedTriangle};
+
+    // A shadow map of a directional light shining straight down on the
+    // origin, with casters covering the given texels, as offsets from
+    // the texel the origin falls in
+    fn map_with_casters(casters: &[(isize, isize)], z: f32, pcf_radius: usize) -> ShadowMap {
+        let size = 16;
+        let light = Light::directional(Point3::new([0.0, -1.0, 0.0]));
+        let camera = light.shadow_camera(Point3::default(), 1.0);
+        let origin = clip_to_screen(camera.project_to_clip(Point3::default()), (size, size));
+        let (x, y) = origin.pixel();
+
+        // Two triangles covering each texel
+        let point = |x: isize, y: isize| ProjectedPoint {
+            x: x as f32,
+            y: y as f32,
+            z,
+            w: 1.0,
+        };
+        let triangles: Vec<ProjectedTriangle> = casters
+            .iter()
+            .flat_map(|&(dx, dy)| {
+                let (x, y) = (x + dx, y + dy);
+                [
+                    ProjectedTriangle {
+                        v0: point(x, y),
+                        v1: point(x + 1, y),
+                        v2: point(x + 1, y + 1),
+                    },
+                    ProjectedTriangle {
+                        v0: point(x, y),
+                        v1: point(x + 1, y + 1),
+                        v2: point(x, y + 1),
+                    },
+                ]
+            })
+            .collect();
+
+        let mut depth = ScreenBuffer::new(size, size);
+        depth.clear(Rgb::BLACK);
+        depth.fill_projected_triangles_depth(&triangles);
+
+        ShadowMap {
+            depth,
+            camera,
+            size,
+            texel_size: 2.0 / size as f32,
+            pcf_radius,
+        }
+    }
+
+    #[test]
+    fn visibility_without_filtering_tests_one_texel() {
+        let origin = Point3::default();
+
+        let shadow_map = map_with_casters(&[(0, 0)], 0.1, 0);
+        assert_eq!(shadow_map.visibility(origin), 0.0);
+
+        // Casters behind the point, or only next to it, leave it lit
+        let shadow_map = map_with_casters(&[(0, 0)], 0.9, 0);
+        assert_eq!(shadow_map.visibility(origin), 1.0);
+        let shadow_map = map_with_casters(&[(-1, 0), (1, 1)], 0.1, 0);
+        assert_eq!(shadow_map.visibility(origin), 1.0);
+    }
+
+    #[test]
+    fn visibility_averages_filtered_texels() {
+        let origin = Point3::default();
+
+        let shadow_map = map_with_casters(&[(-1, 0), (1, 1)], 0.1, 1);
+        assert_eq!(shadow_map.visibility(origin), 7.0 / 9.0);
+
+        // Texels past the radius are left out
+        let shadow_map = map_with_casters(&[(-1, 0), (2, 2)], 0.1, 1);
+        assert_eq!(shadow_map.visibility(origin), 8.0 / 9.0);
+
+        let shadow_map = map_with_casters(&[(-2, 0), (2, 2)], 0.1, 2);
+        assert_eq!(shadow_map.visibility(origin), 23.0 / 25.0);
+    }
+}
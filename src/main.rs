@@ -1,14 +1,25 @@
-use crate::world::camera::Camera;
+use crate::color::Rgb;
+use crate::world::camera::{Camera, Projection};
 use crate::world::{Object, Point3};
 use core::f32;
-use std::{env, path, process};
-
+use std::{env, process};
+
+mod ambient_occlusion;
+mod cli;
+mod color;
+mod fog;
+mod hdr;
+mod lighting;
 mod matrix;
 mod obj;
+mod outline;
 mod ply;
+mod post_process;
 mod render;
 mod scene;
 mod screen_buffer;
+mod shading;
+mod stage;
 mod world;
 
 // in pixels
@@ -16,39 +27,12 @@ const WIDTH: usize = 750;
 const HEIGHT: usize = 750;
 const ASPECT_RATIO: f32 = WIDTH as f32 / HEIGHT as f32;
 
-fn run() -> Result<(), String> {
-    let args: Vec<String> = env::args().collect();
-
-    if args.len() == 1 {
-        return Err("missing file argument".to_string());
-    }
-
-    let file_name = &args[1];
-    if !path::Path::new(file_name).exists() {
-        return Err(format!("file \"{}\" not found", file_name));
-    }
-
-    let mut fps: u64 = 30;
-    if args.len() >= 3 {
-        let fps_string = &args[2];
-        match fps_string.parse::<u64>() {
-            Ok(val) => fps = val,
-            Err(_) => {
-                return Err(format!("invalid fps: {}", fps_string));
-            }
-        };
-    }
+// In sRGB
+const BACKGROUND_COLOR: u32 = 0xf7ffff;
 
-    let mut scale: f32 = 0.0;
-    if args.len() >= 4 {
-        let scale_string = &args[3];
-        match scale_string.parse::<f32>() {
-            Ok(val) => scale = val,
-            Err(_) => {
-                return Err(format!("invalid scale: {}", scale_string));
-            }
-        };
-    }
+fn run() -> Result<(), String> {
+    let args = cli::parse(&env::args().collect::<Vec<String>>())?;
+    let file_name = &args.file_name;
 
     let mut object: Object = if file_name.ends_with(".ply") {
         match ply::load(file_name) {
@@ -62,47 +46,83 @@ fn run() -> Result<(), String> {
         }
     };
 
-    if scale != 0.0 {
-        object.scale(scale);
+    if args.scale != 0.0 {
+        object.scale(args.scale);
     } else {
         object.normalize_size(5.0);
     }
+    object.fade(args.opacity);
+    object.set_shading(args.shading);
 
     println!("Object details: {}", object);
+    let stage = stage::Stage::new(object, args.lights, args.lighting);
 
     let mut cam = Camera::new(Point3::new([3.0, 2.0, -2.0]), ASPECT_RATIO);
+    cam.set_projection(args.projection);
+    cam.set_fov_y(args.fov.to_radians());
+    cam.set_view_height(args.view_height);
+    cam.set_clip_planes(args.near, args.far);
     cam.point_to(Point3::new([0.0, 0.0, 4.0]));
     cam.update();
 
+    let background_color = Rgb::from_srgb(BACKGROUND_COLOR);
+    let fog_color = args.fog_color.unwrap_or(background_color);
+    let mut options = render::RenderOptions {
+        mode: args.render_mode,
+        line_style: args.line_style,
+        fog: args.fog.map(|mode| fog::Fog {
+            mode,
+            color: fog_color,
+        }),
+        transparency: args.transparency,
+        back_faces: args.back_faces,
+        outline: args.outline,
+    };
+
     let now = std::time::SystemTime::now();
     let mut scene = scene::Scene::new(
-        object,
+        stage,
         "Shapes - ESC to quit",
         (WIDTH, HEIGHT),
-        fps.max(1),
+        args.fps.max(1),
         cam,
-        0xf7ffff,
-        |_, window, cam, delta| {
+        background_color,
+        move |_, window, cam, delta| {
             handle_camera_controls(
                 window,
                 cam,
                 0.01 * (delta.as_millis() as f32),
                 0.001 * (delta.as_millis() as f32),
             );
+            handle_render_controls(window, &mut options);
 
             let elapsed = now.elapsed().unwrap().as_secs_f32();
 
-            render::ObjectOrientation {
-                position: Point3::new([0.0, 0.0, 4.0]),
-                rotation: (0.0, f32::to_radians(elapsed * 20.0), f32::to_radians(-90.0)),
+            render::RenderState {
+                orientation: render::ObjectOrientation {
+                    position: Point3::new([0.0, 0.0, 4.0]),
+                    rotation: (0.0, f32::to_radians(elapsed * 20.0), f32::to_radians(-90.0)),
+                    ..Default::default()
+                },
+                options,
             }
         },
     );
 
-    if fps == 0 {
-        let frame = scene.draw_and_export_frame(render::ObjectOrientation {
-            position: Point3::new([0.0, 0.0, 4.0]),
-            rotation: (0.0, 0.0, f32::to_radians(0.0)),
+    scene.set_anti_aliasing(args.anti_aliasing, args.aa_filter);
+    scene.set_ambient_occlusion(args.ambient_occlusion);
+    scene.set_bloom(args.bloom);
+    scene.set_tone_mapping(args.tone_mapping);
+    scene.set_effects(args.effects);
+
+    if args.fps == 0 {
+        let frame = scene.draw_and_export_frame(render::RenderState {
+            orientation: render::ObjectOrientation {
+                position: Point3::new([0.0, 0.0, 4.0]),
+                rotation: (0.0, 0.0, f32::to_radians(0.0)),
+                ..Default::default()
+            },
+            options,
         });
 
         let buf_data = &rgb8_to_u8_vec(frame)[..];
@@ -114,9 +134,16 @@ fn run() -> Result<(), String> {
             image::ColorType::Rgb8,
         );
 
-        match save_res {
-            Ok(_) => Ok(()),
-            Err(e) => Err(e.to_string()),
+        if let Err(e) = save_res {
+            return Err(e.to_string());
+        }
+
+        match &args.hdr_output {
+            Some(path) => match hdr::write_pfm(path, scene.hdr_frame(), WIDTH, HEIGHT) {
+                Ok(_) => Ok(()),
+                Err(e) => Err(format!("failed to save {}: {}", path, e)),
+            },
+            None => Ok(()),
         }
     } else {
         scene.run();
@@ -211,6 +238,15 @@ fn handle_camera_controls(
         ]));
     }
 
+    // Projection toggle - P key
+    let toggle_projection = window.is_key_pressed(minifb::Key::P, minifb::KeyRepeat::No);
+    if toggle_projection {
+        camera.set_projection(match camera.projection() {
+            Projection::Perspective => Projection::Orthographic,
+            Projection::Orthographic => Projection::Perspective,
+        });
+    }
+
     // Rotation controls - Arrow keys
     let mut rotation_delta = (0.0f32, 0.0f32, 0.0f32);
 
@@ -258,6 +294,18 @@ fn handle_camera_controls(
     }
 }
 
+fn handle_render_controls(window: &minifb::Window, options: &mut render::RenderOptions) {
+    // Render mode - M key cycles solid, wireframe, hidden line, and solid with wireframe
+    if window.is_key_pressed(minifb::Key::M, minifb::KeyRepeat::No) {
+        options.mode = options.mode.next();
+    }
+
+    // Back faces - B key cycles culled, shown, and highlighted
+    if window.is_key_pressed(minifb::Key::B, minifb::KeyRepeat::No) {
+        options.back_faces = options.back_faces.next();
+    }
+}
+
 fn main() {
     process::exit(match run() {
         Ok(_) => 0,
@@ -0,0 +1,59 @@
+use crate::color::Rgb;
+use crate::screen_buffer::{view_depths, ScreenBuffer};
+use crate::world::camera::Camera;
+
+// Samples at least this much closer to the camera than the average of their
+// neighbors, as a fraction of their depth, are on an outline
+const DEPTH_EDGE_THRESHOLD: f32 = 0.05;
+
+// Lines drawn along the outline of an object, in the style of a technical
+// illustration
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Outline {
+    pub color: Rgb,
+    pub crease_angle: f32, // in radians, edges between faces bent further are outlined
+}
+
+impl Default for Outline {
+    fn default() -> Self {
+        Outline {
+            color: Rgb::BLACK,
+            crease_angle: 40f32.to_radians(),
+        }
+    }
+}
+
+// Colors samples where the z buffer jumps, which catches outlines that no
+// single edge of a mesh accounts for, like where surfaces cut through each
+// other or are clipped by the near plane.
+//
+// The depth of a sample is compared with the average of its two neighbors
+// across each axis, in view space. Slopes and flat surfaces at any angle are
+// halfway in depth between their neighbors, while a sample in front of a
+// jump is closer to the camera than the average. Only the front side of a
+// jump is colored, which keeps lines one sample wide.
+pub fn draw_depth_edges(screen: &mut ScreenBuffer, camera: &Camera, outline: &Outline) {
+    let (width, height) = screen.raster_size();
+    let (buffer, z_buffer) = screen.samples_mut();
+    let depths = view_depths(z_buffer, camera);
+
+    for y in 1..height.saturating_sub(1) {
+        for x in 1..width.saturating_sub(1) {
+            let index = y * width + x;
+            let depth = depths[index];
+            if depth.is_infinite() {
+                continue;
+            }
+
+            let neighbors = [(index - 1, index + 1), (index - width, index + width)];
+            let on_edge = neighbors.iter().any(|&(a, b)| {
+                let average = (depths[a] + depths[b]) / 2.0;
+                average - depth > depth * DEPTH_EDGE_THRESHOLD
+            });
+
+            if on_edge {
+                buffer[index] = outline.color;
+            }
+        }
+    }
+}
@@ -47,6 +47,7 @@ pub fn load(path: &str) -> Result<Object, Error> {
 
     let face_count = ply.header.elements["face"].count;
     let mut face_indexes: Vec<Vec<usize>> = Vec::with_capacity(face_count);
+    let mut face_opacities: Vec<f32> = Vec::with_capacity(face_count);
 
     for mut f in ply.payload.remove("face").unwrap() {
         let vi = f.remove(vertex_index_name);
@@ -74,10 +75,20 @@ pub fn load(path: &str) -> Result<Object, Error> {
             }
 
             face_indexes.push(face_vec);
+
+            // Faces are opaque unless they have an alpha or opacity property
+            let opacity = f
+                .get("alpha")
+                .or_else(|| f.get("opacity"))
+                .and_then(scalar_to_opacity)
+                .unwrap_or(1.0);
+            face_opacities.push(opacity);
         }
     }
 
-    Ok(Object::new(vertices, face_indexes))
+    let mut object = Object::new(vertices, face_indexes);
+    object.set_face_opacities(face_opacities);
+    Ok(object)
 }
 
 fn conv_vec_to_usize<T>(v: Vec<T>) -> Vec<usize>
@@ -89,6 +100,24 @@ where
         .collect()
 }
 
+// Integer opacities span their type's range like color channels, up to its
+// largest value, while floating point opacities are in [0, 1]. Negative
+// opacities are clamped to 0.
+fn scalar_to_opacity(prop: &Property) -> Option<f32> {
+    let opacity = match *prop {
+        Property::Char(n) => n as f32 / i8::MAX as f32,
+        Property::UChar(n) => n as f32 / u8::MAX as f32,
+        Property::Short(n) => n as f32 / i16::MAX as f32,
+        Property::UShort(n) => n as f32 / u16::MAX as f32,
+        Property::Int(n) => n as f32 / i32::MAX as f32,
+        Property::UInt(n) => n as f32 / u32::MAX as f32,
+        Property::Float(n) => n,
+        Property::Double(n) => n as f32,
+        _ => return None,
+    };
+    Some(opacity.clamp(0.0, 1.0))
+}
+
 fn scalar_to_float(prop: &Property) -> Option<f32> {
     match *prop {
         Property::Float(n) => Some(n),
@@ -102,3 +131,49 @@ fn scalar_to_float(prop: &Property) -> Option<f32> {
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integer_opacities_span_their_range() {
+        let opaque = [
+            Property::Char(i8::MAX),
+            Property::UChar(u8::MAX),
+            Property::Short(i16::MAX),
+            Property::UShort(u16::MAX),
+            Property::Int(i32::MAX),
+            Property::UInt(u32::MAX),
+            Property::Float(1.0),
+            Property::Double(1.0),
+        ];
+        for prop in &opaque {
+            assert_eq!(scalar_to_opacity(prop), Some(1.0), "{:?}", prop);
+        }
+
+        let half = [
+            Property::Char(64),
+            Property::UChar(128),
+            Property::Short(1 << 14),
+            Property::UShort(1 << 15),
+            Property::Int(1 << 30),
+            Property::UInt(1 << 31),
+            Property::Float(0.5),
+            Property::Double(0.5),
+        ];
+        for prop in &half {
+            let opacity = scalar_to_opacity(prop).unwrap();
+            assert!((opacity - 0.5).abs() < 0.01, "{:?} gave {}", prop, opacity);
+        }
+    }
+
+    #[test]
+    fn out_of_range_opacities_are_clamped() {
+        assert_eq!(scalar_to_opacity(&Property::Char(-5)), Some(0.0));
+        assert_eq!(scalar_to_opacity(&Property::Int(i32::MIN)), Some(0.0));
+        assert_eq!(scalar_to_opacity(&Property::Float(-0.5)), Some(0.0));
+        assert_eq!(scalar_to_opacity(&Property::Double(2.0)), Some(1.0));
+        assert_eq!(scalar_to_opacity(&Property::ListUChar(vec![255])), None);
+    }
+}
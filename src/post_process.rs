@@ -0,0 +1,212 @@
+use crate::color::{linear_to_srgb, srgb_to_linear, Rgb};
+use crate::screen_buffer::{view_depths, ScreenBuffer};
+use crate::world::camera::Camera;
+
+// A drawn frame, as seen by post-processing effects
+pub struct Frame<'a> {
+    pub colors: &'a [Rgb], // color of every pixel in linear light and [0, 1], row by row
+    pub depths: &'a [f32], // view space depth of every pixel, infinite where nothing was drawn
+    pub width: usize,
+    pub height: usize,
+}
+
+impl<'a> Frame<'a> {
+    // Color of a pixel, extending the edges of the frame past them
+    pub fn color(&self, x: isize, y: isize) -> Rgb {
+        let x = x.clamp(0, self.width as isize - 1) as usize;
+        let y = y.clamp(0, self.height as isize - 1) as usize;
+        self.colors[y * self.width + x]
+    }
+
+    // Depth of a pixel, extending the edges of the frame past them
+    pub fn depth(&self, x: isize, y: isize) -> f32 {
+        let x = x.clamp(0, self.width as isize - 1) as usize;
+        let y = y.clamp(0, self.height as isize - 1) as usize;
+        self.depths[y * self.width + x]
+    }
+}
+
+// A post-processing effect, which makes a new color for every pixel of a
+// frame. output has one color per pixel, row by row.
+pub trait Effect {
+    fn apply(&self, frame: &Frame, output: &mut [Rgb]);
+}
+
+// Runs effects over a drawn and tone mapped frame in order, each one seeing
+// what the previous one made
+pub fn apply_effects(screen: &mut ScreenBuffer, camera: &Camera, effects: &[Box<dyn Effect>]) {
+    if effects.is_empty() {
+        return;
+    }
+
+    let (width, height) = screen.size();
+    let depths = view_depths(&screen.pixel_depths(), camera);
+    let mut colors = screen.pixels().to_vec();
+    let mut output = vec![Rgb::BLACK; colors.len()];
+
+    for effect in effects {
+        let frame = Frame {
+            colors: &colors,
+            depths: &depths,
+            width,
+            height,
+        };
+        effect.apply(&frame, &mut output);
+        std::mem::swap(&mut colors, &mut output);
+    }
+
+    screen.pixels_mut().copy_from_slice(&colors);
+}
+
+// Removes all color, keeping luminance
+pub struct Grayscale;
+
+impl Effect for Grayscale {
+    fn apply(&self, frame: &Frame, output: &mut [Rgb]) {
+        for (out, &color) in output.iter_mut().zip(frame.colors.iter()) {
+            *out = Rgb::gray(color.luminance());
+        }
+    }
+}
+
+// Remaps every channel through a curve through control points, each taking
+// an input level to an output level, both in [0, 1]. Levels between control
+// points are interpolated linearly, and levels past the first or last point
+// take its output level.
+//
+// Levels are in sRGB like in image editors, so that halfway along the curve
+// looks halfway between black and white.
+pub struct ColorCurves {
+    points: Vec<(f32, f32)>,
+}
+
+impl ColorCurves {
+    pub fn new(points: &[(f32, f32)]) -> ColorCurves {
+        let mut points = points.to_vec();
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        ColorCurves { points }
+    }
+
+    fn level(&self, x: f32) -> f32 {
+        let points = &self.points;
+        let y = match points.iter().position(|p| p.0 >= x) {
+            _ if points.is_empty() => x, // no points leave levels as they are
+            Some(0) => points[0].1,
+            Some(i) => {
+                let (a, b) = (points[i - 1], points[i]);
+                a.1 + (b.1 - a.1) * (x - a.0) / (b.0 - a.0)
+            }
+            None => points[points.len() - 1].1,
+        };
+        y.clamp(0.0, 1.0)
+    }
+}
+
+impl Effect for ColorCurves {
+    fn apply(&self, frame: &Frame, output: &mut [Rgb]) {
+        for (out, &color) in output.iter_mut().zip(frame.colors.iter()) {
+            *out = color.map(|c| srgb_to_linear(self.level(linear_to_srgb(c.clamp(0.0, 1.0)))));
+        }
+    }
+}
+
+// Darkens the frame towards its corners
+pub struct Vignette {
+    pub strength: f32, // darkening at the corners, in [0, 1]
+    pub radius: f32,   // where darkening starts, from 0 at the center to 1 at the corners
+}
+
+impl Effect for Vignette {
+    fn apply(&self, frame: &Frame, output: &mut [Rgb]) {
+        let center_x = frame.width as f32 / 2.0;
+        let center_y = frame.height as f32 / 2.0;
+        let corner = (center_x * center_x + center_y * center_y).sqrt();
+
+        for (i, (out, &color)) in output.iter_mut().zip(frame.colors.iter()).enumerate() {
+            let dx = (i % frame.width) as f32 + 0.5 - center_x;
+            let dy = (i / frame.width) as f32 + 0.5 - center_y;
+            let distance = (dx * dx + dy * dy).sqrt() / corner;
+
+            // Smoothly from nothing at the radius to full strength at the corners
+            let t = ((distance - self.radius) / (1.0 - self.radius)).clamp(0.0, 1.0);
+            let darkening = self.strength * t * t * (3.0 - 2.0 * t);
+
+            *out = color * (1.0 - darkening);
+        }
+    }
+}
+
+// Exaggerates differences between neighboring pixels, by adding to each
+// pixel its difference from the average around it (unsharp masking)
+//
+// ref: https://en.wikipedia.org/wiki/Unsharp_masking
+pub struct Sharpen {
+    pub amount: f32,
+}
+
+impl Effect for Sharpen {
+    fn apply(&self, frame: &Frame, output: &mut [Rgb]) {
+        for y in 0..frame.height {
+            for x in 0..frame.width {
+                let (x, y) = (x as isize, y as isize);
+
+                let mut average = Rgb::BLACK;
+                for ny in y - 1..=y + 1 {
+                    for nx in x - 1..=x + 1 {
+                        average += frame.color(nx, ny) * (1.0 / 9.0);
+                    }
+                }
+
+                let color = frame.color(x, y);
+                let sharpened = color + (color - average) * self.amount;
+                output[y as usize * frame.width + x as usize] = sharpened.map(|c| c.max(0.0));
+            }
+        }
+    }
+}
+
+// Length of the gradient of f around a pixel (Sobel operator). It is at
+// most 4 times the largest difference of f between pixels.
+//
+// ref: https://en.wikipedia.org/wiki/Sobel_operator
+fn sobel<F: Fn(isize, isize) -> f32>(f: F) -> f32 {
+    let gx = (f(1, -1) + 2.0 * f(1, 0) + f(1, 1)) - (f(-1, -1) + 2.0 * f(-1, 0) + f(-1, 1));
+    let gy = (f(-1, 1) + 2.0 * f(0, 1) + f(1, 1)) - (f(-1, -1) + 2.0 * f(0, -1) + f(1, -1));
+    (gx * gx + gy * gy).sqrt()
+}
+
+// Replaces the frame with its edges, drawn dark on white by how sharply
+// luminance or depth changes across them. Depth is compared by its inverse,
+// relative to the closest pixel around, so that edges are as strong near
+// and far away, and background pixels simply have an inverse depth of 0.
+// Luminance is compared and edges are drawn in sRGB levels, so that edges
+// are as strong as they look.
+pub struct EdgeDetect;
+
+impl Effect for EdgeDetect {
+    fn apply(&self, frame: &Frame, output: &mut [Rgb]) {
+        for y in 0..frame.height {
+            for x in 0..frame.width {
+                let (x, y) = (x as isize, y as isize);
+
+                let luminance_edge =
+                    sobel(|dx, dy| linear_to_srgb(frame.color(x + dx, y + dy).luminance())) / 4.0;
+
+                let inverse_depth = |dx: isize, dy: isize| 1.0 / frame.depth(x + dx, y + dy);
+                let closest = (-1..=1)
+                    .flat_map(|dy| (-1..=1).map(move |dx| (dx, dy)))
+                    .map(|(dx, dy)| inverse_depth(dx, dy))
+                    .fold(0.0, f32::max);
+                let depth_edge = if closest > 0.0 {
+                    sobel(inverse_depth) / closest / 4.0
+                } else {
+                    0.0
+                };
+
+                let edge = luminance_edge.max(depth_edge).min(1.0);
+                output[y as usize * frame.width + x as usize] =
+                    Rgb::gray(srgb_to_linear(1.0 - edge));
+            }
+        }
+    }
+}
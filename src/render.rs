@@ -1,95 +1,94 @@
+use crate::color::Rgb;
+use crate::fog::{DepthCue, Fog};
+use crate::lighting::Lighting;
+use crate::matrix::Matrix;
+use crate::outline::{draw_depth_edges, Outline};
 use crate::scene::Renderer;
 use crate::screen_buffer::ScreenBuffer;
-use crate::world::camera::Camera;
-use crate::world::projection::{projected_point_to_screen, ProjectedPoint, ProjectedTriangle};
-use crate::world::three_dim::{make_rotation_matrix, rotate_point_about_origin_with_matrix};
-use crate::world::{projection_to_screen, Object, Point3};
+use crate::shading::Shading;
+use crate::world::camera::{Camera, Projection};
+use crate::world::projection::{
+    clip_line, clip_polygon, clip_polygon_with_normals, clip_to_screen, in_depth_range,
+    ProjectedPoint, ProjectedTriangle,
+};
+use crate::world::three_dim::{make_model_matrix, make_normal_matrix};
+use crate::world::{Object, Point3, Point4};
 
 const RENDER_DEBUG: bool = true;
 
+// Depth offset that keeps lines and points lying on a surface in front of it
+const LINE_DEPTH_BIAS: f32 = 5e-4;
+
 impl ScreenBuffer {
-    // Attempts to bring a point inside the screen along a line
-    //
-    // For example:
-    //                    +
-    //                   /
-    //                  /
-    //       +---------+----------------+
-    //       |        /                 |
-    //       |       /                  |
-    //       |      /                   |
+    // Clips the segment p1-->p2 to the screen, returning the range of the
+    // segment parameter t, where p(t) = p1 + t * (p2 - p1), that lies on
+    // screen. Lines may leave the screen through any edge or corner.
     //
-    // A line is projected through the original point to the intersection with the window.
-    fn bring_inside(&self, p: (isize, isize), slope: f32) -> (isize, isize) {
-        // precondition: point is outside of screen
-
-        if slope.is_nan() {
-            // dy = dx = 0 leads to indeterminate form 0.0/0.0 = NaN
-            return p;
-        }
-
-        let is_vertical: bool = slope.is_infinite();
-        let below = p.1 < 0;
-        let above = p.1 >= self.height() as isize;
-        let left = p.0 < 0;
-        let right = p.0 >= self.width() as isize;
-
-        if (above || below) && slope == 0.0 {
-            return p;
-        }
-
-        if (left || right) && is_vertical {
-            return p;
-        }
-
-        let old_x = p.0 as f32;
-        let old_y = p.1 as f32;
-        let mut new_x = old_x;
-        let mut new_y = old_y;
-
-        if is_vertical {
-            if below {
-                new_y = 0.0;
-            } else if above {
-                new_y = (self.height() - 1) as f32;
+    // ref: https://en.wikipedia.org/wiki/Liang%E2%80%93Barsky_algorithm
+    pub fn clip_segment(&self, p1: (f32, f32), p2: (f32, f32)) -> Option<(f32, f32)> {
+        let dx = p2.0 - p1.0;
+        let dy = p2.1 - p1.1;
+        let (width, height) = self.raster_size();
+        let max_x = width as f32;
+        let max_y = height as f32;
+
+        // The segment is inside of each edge where p * t <= q
+        let edges = [
+            (-dx, p1.0),        // left
+            (dx, max_x - p1.0), // right
+            (-dy, p1.1),        // top
+            (dy, max_y - p1.1), // bottom
+        ];
+
+        let mut t0: f32 = 0.0;
+        let mut t1: f32 = 1.0;
+
+        for &(p, q) in edges.iter() {
+            if p == 0.0 {
+                // parallel to the edge, so either entirely inside or outside of it
+                if q < 0.0 {
+                    return None;
+                }
+                continue;
             }
-        } else if left || right {
-            if left {
-                new_x = 0.0;
+
+            let t = q / p;
+            if p < 0.0 {
+                // entering through this edge
+                t0 = t0.max(t);
             } else {
-                new_x = (self.width() as isize - 1) as f32;
+                // leaving through this edge
+                t1 = t1.min(t);
             }
-            let dx = new_x - old_x;
-            new_y = dx * slope + old_y;
-        } else if above || below {
-            // at this point above || below should always be true, but whatever
-            if below {
-                new_y = 0.0
-            } else {
-                new_y = (self.height() as isize - 1) as f32;
+
+            if t0 > t1 {
+                return None;
             }
-            let dy = new_y - old_y;
-            new_x = dy / slope + old_x;
         }
 
-        (new_x as isize, new_y as isize)
+        Some((t0, t1))
     }
 
-    // adapted from http://www.sunshine2k.de/java.html#bresenham
-    pub fn draw_line(&mut self, mut p1: (isize, isize), mut p2: (isize, isize), color: u32) {
-        let slope: f32 = (p2.1 as f32 - p1.1 as f32) / (p2.0 as f32 - p1.0 as f32);
-
-        if self.outside_screen(p1) {
-            p1 = self.bring_inside(p1, slope);
-        }
+    // Converts a point on screen to the pixel containing it. Points on the
+    // right and bottom screen edges belong to the last column and row.
+    fn pixel_on_screen(&self, p: (f32, f32)) -> (isize, isize) {
+        let (width, height) = self.raster_size();
+        (
+            (p.0.floor() as isize).clamp(0, width as isize - 1),
+            (p.1.floor() as isize).clamp(0, height as isize - 1),
+        )
+    }
 
-        if self.outside_screen(p2) {
-            p2 = self.bring_inside(p2, slope);
-        }
+    // adapted from http://www.sunshine2k.de/java.html#bresenham
+    pub fn draw_line(&mut self, p1: (f32, f32), p2: (f32, f32), color: Rgb) {
+        let (t0, t1) = match self.clip_segment(p1, p2) {
+            Some(range) => range,
+            None => return,
+        };
 
-        if self.outside_screen(p1) || self.outside_screen(p2) {
-            return;
-        }
+        let lerp = |t: f32| (p1.0 + (p2.0 - p1.0) * t, p1.1 + (p2.1 - p1.1) * t);
+        let p1 = self.pixel_on_screen(lerp(t0));
+        let p2 = self.pixel_on_screen(lerp(t1));
 
         let mut x = p1.0;
         let mut y = p1.1;
@@ -130,138 +129,862 @@ impl ScreenBuffer {
 
         self.set_pixel_i(p2, color);
     }
+
+    // Draws a line that is optionally hidden by anything closer to the camera
+    // in the z buffer, and faded into fog with depth cueing. Normalized device
+    // depth is affine in screen space, so it is interpolated linearly along
+    // the line.
+    pub fn draw_line_depth(
+        &mut self,
+        p1: &ProjectedPoint,
+        p2: &ProjectedPoint,
+        color: Rgb,
+        depth_test: Option<DepthBias>,
+        depth_cue: Option<&DepthCue>,
+    ) {
+        let (t0, t1) = match self.clip_segment((p1.x, p1.y), (p2.x, p2.y)) {
+            Some(range) => range,
+            None => return,
+        };
+
+        let dx = p2.x - p1.x;
+        let dy = p2.y - p1.y;
+        let dz = p2.z - p1.z;
+        let steps = (f32::max(dx.abs(), dy.abs()) * (t1 - t0)).ceil().max(1.0) as usize;
+
+        for i in 0..=steps {
+            let t = t0 + (t1 - t0) * (i as f32 / steps as f32);
+            let pixel = self.pixel_on_screen((p1.x + dx * t, p1.y + dy * t));
+            let z = p1.z + dz * t;
+            let color = match depth_cue {
+                Some(depth_cue) => depth_cue.apply(color, z),
+                None => color,
+            };
+
+            match depth_test {
+                Some(bias) => self.set_pixel_depth_tested(pixel, bias.apply(z), color),
+                None => self.set_pixel_i(pixel, color),
+            };
+        }
+    }
+
+    // Draws a line with the given style, hidden by anything closer to the
+    // camera in the z buffer when given a depth bias, and faded into fog with
+    // depth cueing. Plain one pixel lines take the faster stepping paths when
+    // not anti-aliasing the screen.
+    pub fn draw_styled_line(
+        &mut self,
+        p1: &ProjectedPoint,
+        p2: &ProjectedPoint,
+        color: Rgb,
+        style: &LineStyle,
+        depth_test: Option<DepthBias>,
+        depth_cue: Option<&DepthCue>,
+    ) {
+        let factor = self.sample_factor();
+
+        if factor == 1 && style.width <= 1.0 && !style.anti_aliased {
+            if depth_test.is_some() || depth_cue.is_some() {
+                self.draw_line_depth(p1, p2, color, depth_test, depth_cue);
+            } else {
+                self.draw_line((p1.x, p1.y), (p2.x, p2.y), color);
+            }
+        } else {
+            // Widths are in pixels, which span several samples when anti-aliasing
+            let raster_style = LineStyle {
+                width: style.width * factor as f32,
+                ..*style
+            };
+            self.draw_wide_line(p1, p2, color, &raster_style, depth_test, depth_cue);
+        }
+    }
+
+    // Draws a line as a band of the style's width, shaped at its ends by the
+    // style's cap. Each pixel is covered by how far its center lies inside of
+    // the band: anti-aliased lines blend partially covered pixels with what is
+    // already on screen, while aliased lines fill pixels whose centers are
+    // inside.
+    //
+    // Pixels are visited column by column along the major axis of the line,
+    // only as far out along the minor axis as the band can reach.
+    fn draw_wide_line(
+        &mut self,
+        p1: &ProjectedPoint,
+        p2: &ProjectedPoint,
+        color: Rgb,
+        style: &LineStyle,
+        depth_test: Option<DepthBias>,
+        depth_cue: Option<&DepthCue>,
+    ) {
+        let dx = p2.x - p1.x;
+        let dy = p2.y - p1.y;
+        let length = (dx * dx + dy * dy).sqrt();
+        let (dir_x, dir_y) = if length > f32::EPSILON {
+            (dx / length, dy / length)
+        } else {
+            (1.0, 0.0)
+        };
+
+        let half_width = style.width / 2.0;
+        let cap_length = match style.cap {
+            LineCap::Butt => 0.0,
+            LineCap::Round | LineCap::Square => half_width,
+        };
+
+        // Coverage of a pixel whose center is at signed distance d outside of an edge
+        let coverage = |d: f32| -> f32 {
+            if style.anti_aliased {
+                (0.5 - d).clamp(0.0, 1.0)
+            } else if d <= 0.0 {
+                1.0
+            } else {
+                0.0
+            }
+        };
+
+        // Work in (major, minor) coordinates so that both orientations share a loop
+        let x_major = dx.abs() >= dy.abs();
+        let swap = |p: (f32, f32)| if x_major { p } else { (p.1, p.0) };
+        let (mut a, mut b) = (swap((p1.x, p1.y)), swap((p2.x, p2.y)));
+        if a.0 > b.0 {
+            std::mem::swap(&mut a, &mut b);
+        }
+        let (width, height) = self.raster_size();
+        let (major_size, minor_size) = swap((width as f32, height as f32));
+
+        let slope = if b.0 - a.0 > f32::EPSILON {
+            (b.1 - a.1) / (b.0 - a.0)
+        } else {
+            0.0
+        };
+        let reach = half_width + cap_length + 1.0;
+        let minor_reach = reach * (1.0 + slope.abs());
+
+        let major_start = (a.0 - reach).floor().max(0.0) as isize;
+        let major_end = (b.0 + reach).ceil().min(major_size - 1.0) as isize;
+
+        for major in major_start..=major_end {
+            let along_major = (major as f32 + 0.5).clamp(a.0, b.0);
+            let minor_center = a.1 + (along_major - a.0) * slope;
+            let minor_start = (minor_center - minor_reach).floor().max(0.0) as isize;
+            let minor_end = (minor_center + minor_reach).ceil().min(minor_size - 1.0) as isize;
+
+            for minor in minor_start..=minor_end {
+                let pixel = if x_major {
+                    (major, minor)
+                } else {
+                    (minor, major)
+                };
+
+                // Position of the pixel center relative to the line
+                let rel_x = pixel.0 as f32 + 0.5 - p1.x;
+                let rel_y = pixel.1 as f32 + 0.5 - p1.y;
+                let along = rel_x * dir_x + rel_y * dir_y;
+                let across = (rel_x * dir_y - rel_y * dir_x).abs();
+                let beyond_end = f32::max(-along, along - length);
+
+                let alpha = match style.cap {
+                    LineCap::Butt => coverage(across - half_width) * coverage(beyond_end),
+                    LineCap::Square => {
+                        coverage(across - half_width) * coverage(beyond_end - half_width)
+                    }
+                    LineCap::Round if beyond_end > 0.0 => {
+                        coverage((beyond_end * beyond_end + across * across).sqrt() - half_width)
+                    }
+                    LineCap::Round => coverage(across - half_width),
+                };
+
+                if alpha <= 0.0 {
+                    continue;
+                }
+
+                let t = if length > f32::EPSILON {
+                    (along / length).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                let z = p1.z + (p2.z - p1.z) * t;
+                let color = match depth_cue {
+                    Some(depth_cue) => depth_cue.apply(color, z),
+                    None => color,
+                };
+
+                let tested_z = depth_test.map(|bias| bias.apply(z));
+                self.blend_pixel(pixel, tested_z, color.with_alpha(alpha));
+            }
+        }
+    }
+
+    // Draws a single pixel that is hidden by anything closer to the camera in
+    // the z buffer. All samples of the pixel are covered when anti-aliasing.
+    pub fn draw_point_depth(&mut self, p: &ProjectedPoint, color: Rgb, bias: DepthBias) {
+        let factor = self.sample_factor() as isize;
+        let (x, y) = p.pixel();
+        let (first_x, first_y) = (x - x.rem_euclid(factor), y - y.rem_euclid(factor));
+
+        for sy in first_y..first_y + factor {
+            for sx in first_x..first_x + factor {
+                self.set_pixel_depth_tested((sx, sy), bias.apply(p.z), color);
+            }
+        }
+    }
+}
+
+// Pulls normalized depths towards the camera by LINE_DEPTH_BIAS, depending on
+// how the camera's projection maps view distance to depth
+#[derive(Copy, Clone)]
+pub struct DepthBias {
+    projection: Projection,
 }
 
-struct Triangle {
-    projected: ProjectedTriangle,
-    color: u32,
+impl DepthBias {
+    pub fn new(camera: &Camera) -> DepthBias {
+        DepthBias {
+            projection: camera.projection(),
+        }
+    }
+
+    fn apply(self, z: f32) -> f32 {
+        match self.projection {
+            // Perspective depth is compressed towards the far plane, so the
+            // offset shrinks there to stay a similar fraction of the view
+            // distance at any depth
+            Projection::Perspective => z - LINE_DEPTH_BIAS * (1.0 - z),
+            // Orthographic depth is linear in view distance, so a constant
+            // offset is the same distance at any depth
+            Projection::Orthographic => z - LINE_DEPTH_BIAS,
+        }
+    }
 }
 
-#[derive(Eq, PartialEq)]
-enum SurfaceOrientation {
-    TowardsCamera,
-    AwayFromCamera,
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LineCap {
+    Butt,   // ends flat at the endpoints
+    Round,  // half circle around each endpoint
+    Square, // ends flat, half the width past the endpoints
 }
 
-struct Surface {
-    vertices: Vec<Point3>,
-    camera_surface_dot: f32,
-    orientation: SurfaceOrientation,
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LineStyle {
+    pub width: f32, // in pixels
+    pub anti_aliased: bool,
+    pub cap: LineCap,
 }
 
-#[derive(Default, Copy, Clone, PartialEq)]
+impl Default for LineStyle {
+    fn default() -> Self {
+        LineStyle {
+            width: 1.0,
+            anti_aliased: false,
+            cap: LineCap::Butt,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
 pub struct ObjectOrientation {
     pub position: Point3,
     pub rotation: (f32, f32, f32),
+    pub scale: (f32, f32, f32),
 }
 
-impl Renderer<ObjectOrientation> for Object {
-    fn render(&self, screen: &mut ScreenBuffer, camera: &Camera, state: ObjectOrientation) {
-        // Rendering the object performs the following steps:
-        // 1a. Rotate every surface around the object center
-        // 1b. Transform object to position
-        // 2. Project each surface to z=1 plane
-        // 3. Convert to screen coordinates
-        // 4. Break surfaces into triangles
-        // 5. Raster triangles
-
-        // Rotate surfaces and transform to position
-        // todo: combine actions into single world matrix operation
-        let position: Point3 = state.position;
-        let rotation_matrix =
-            make_rotation_matrix(state.rotation.0, state.rotation.1, state.rotation.2);
-        let surfaces: Vec<Surface> = self
-            .faces()
+impl ObjectOrientation {
+    pub fn model_matrix(&self) -> Matrix<4, 4> {
+        make_model_matrix(self.position, self.rotation, self.scale)
+    }
+
+    pub fn normal_matrix(&self) -> Matrix<3, 3> {
+        make_normal_matrix(self.rotation, self.scale)
+    }
+}
+
+impl Default for ObjectOrientation {
+    fn default() -> Self {
+        ObjectOrientation {
+            position: Point3::default(),
+            rotation: (0.0, 0.0, 0.0),
+            scale: (1.0, 1.0, 1.0),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum RenderMode {
+    #[default]
+    Solid,
+    Wireframe,
+    HiddenLine,     // wireframe with edges hidden by surfaces removed
+    SolidWireframe, // wireframe drawn over the shaded surfaces
+}
+
+impl RenderMode {
+    pub fn next(self) -> RenderMode {
+        match self {
+            RenderMode::Solid => RenderMode::Wireframe,
+            RenderMode::Wireframe => RenderMode::HiddenLine,
+            RenderMode::HiddenLine => RenderMode::SolidWireframe,
+            RenderMode::SolidWireframe => RenderMode::Solid,
+        }
+    }
+}
+
+// How transparent faces are blended over what is behind them
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Transparency {
+    #[default]
+    Sorted, // blended one over another, back to front
+    Weighted, // blended all at once, approximately, without sorting
+}
+
+// What becomes of faces seen from behind, pointing away from the camera
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum BackFaces {
+    #[default]
+    Cull, // not drawn, which hides the far side of closed objects cheaply
+    Show,      // drawn like front faces, so that open surfaces have no holes
+    Highlight, // drawn in BACK_FACE_COLOR, so that flipped faces stand out
+}
+
+impl BackFaces {
+    pub fn next(self) -> BackFaces {
+        match self {
+            BackFaces::Cull => BackFaces::Show,
+            BackFaces::Show => BackFaces::Highlight,
+            BackFaces::Highlight => BackFaces::Cull,
+        }
+    }
+}
+
+#[derive(Default, Copy, Clone, PartialEq)]
+pub struct RenderOptions {
+    pub mode: RenderMode,
+    pub line_style: LineStyle,
+    pub fog: Option<Fog>,
+    pub transparency: Transparency,
+    pub back_faces: BackFaces,
+    pub outline: Option<Outline>,
+}
+
+#[derive(Default, Copy, Clone, PartialEq)]
+pub struct RenderState {
+    pub orientation: ObjectOrientation,
+    pub options: RenderOptions,
+}
+
+const WIREFRAME_COLOR: Rgb = Rgb::BLACK;
+
+// In sRGB, converted where they are drawn
+const OVERLAY_COLOR: u32 = 0x3050c0;
+const BACK_FACE_COLOR: u32 = 0xe03020;
+
+impl Renderer<RenderState> for Object {
+    fn render(&self, screen: &mut ScreenBuffer, camera: &Camera, state: RenderState) {
+        render_object(self, screen, camera, state, None);
+    }
+}
+
+// Renders an object in the mode given by state. Surfaces are shaded by how
+// directly they face the camera, or by lighting when it is given.
+pub fn render_object(
+    object: &Object,
+    screen: &mut ScreenBuffer,
+    camera: &Camera,
+    state: RenderState,
+    lighting: Option<&Lighting>,
+) {
+    let orientation = state.orientation;
+    let options = &state.options;
+    let line_style = &options.line_style;
+    let depth_cue = state.options.fog.map(|fog| DepthCue::new(fog, camera));
+    let transformed = TransformedObject::new(object, camera, orientation);
+
+    match state.options.mode {
+        RenderMode::Solid => {
+            render_surfaces(
+                object,
+                &transformed,
+                screen,
+                camera,
+                false,
+                lighting,
+                options,
+            );
+            render_outline(
+                object,
+                &transformed,
+                screen,
+                camera,
+                options,
+                depth_cue.as_ref(),
+            );
+        }
+        RenderMode::Wireframe => render_wireframe(
+            object,
+            &transformed,
+            screen,
+            WIREFRAME_COLOR,
+            line_style,
+            None,
+            depth_cue.as_ref(),
+        ),
+        RenderMode::HiddenLine => {
+            // Surfaces only fill the z buffer so that they hide the edges behind them
+            render_surfaces(object, &transformed, screen, camera, true, None, options);
+            render_wireframe(
+                object,
+                &transformed,
+                screen,
+                WIREFRAME_COLOR,
+                line_style,
+                Some(DepthBias::new(camera)),
+                depth_cue.as_ref(),
+            );
+        }
+        RenderMode::SolidWireframe => {
+            render_surfaces(
+                object,
+                &transformed,
+                screen,
+                camera,
+                false,
+                lighting,
+                options,
+            );
+            render_outline(
+                object,
+                &transformed,
+                screen,
+                camera,
+                options,
+                depth_cue.as_ref(),
+            );
+            render_wireframe(
+                object,
+                &transformed,
+                screen,
+                Rgb::from_srgb(OVERLAY_COLOR),
+                line_style,
+                Some(DepthBias::new(camera)),
+                depth_cue.as_ref(),
+            );
+        }
+    }
+
+    if RENDER_DEBUG {
+        render_object_origin(orientation.position, screen, camera);
+        render_object_origin(Point3::default(), screen, camera);
+    }
+}
+
+// The vertices and normals of an object, transformed once per frame. Faces
+// and edges refer to the vertices by index, so a vertex shared by several of
+// them is only transformed once.
+pub struct TransformedObject {
+    world: Vec<Point3>,          // vertices in world space
+    clip: Vec<Point4>,           // vertices in clip space
+    normals: Vec<Point3>,        // face normals in world space
+    vertex_normals: Vec<Point3>, // in world space, only when the shading uses them
+}
+
+impl TransformedObject {
+    pub fn new(object: &Object, camera: &Camera, state: ObjectOrientation) -> TransformedObject {
+        // The model matrix takes vertices to world space, and combined with
+        // the camera's matrices takes them straight to clip space
+        let model_matrix = state.model_matrix();
+        let mvp_matrix = camera.view_projection_matrix() * model_matrix;
+        let normal_matrix = state.normal_matrix();
+
+        let world: Vec<Point3> = object
+            .vertices()
+            .iter()
+            .map(|&p| (model_matrix * p.euc_to_hom()).hom_to_euc())
+            .collect();
+
+        let clip = object
+            .vertices()
             .iter()
+            .map(|&p| mvp_matrix * p.euc_to_hom())
+            .collect();
+
+        let normals = object
+            .face_normals()
+            .iter()
+            .map(|&n| (normal_matrix * n).normalize())
+            .collect();
+
+        let vertex_normals = if object.shading().uses_vertex_normals() {
+            object
+                .vertex_normals()
+                .iter()
+                .map(|&n| normal_matrix * n)
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        TransformedObject {
+            world,
+            clip,
+            normals,
+            vertex_normals,
+        }
+    }
+}
+
+pub fn render_surfaces(
+    object: &Object,
+    transformed: &TransformedObject,
+    screen: &mut ScreenBuffer,
+    camera: &Camera,
+    depth_only: bool,
+    lighting: Option<&Lighting>,
+    options: &RenderOptions,
+) {
+    // Rendering the object performs the following steps:
+    // 1. Transform each vertex into world and clip space (TransformedObject)
+    // 2. Order faces, opaque first and then transparent
+    // 3. Cull faces pointing away from the camera, unless back faces are shown
+    // 4. Clip faces against the near and far planes
+    // 5. Perspective divide and convert to screen coordinates
+    // 6. Break faces into triangles
+    // 7. Raster triangles, shading each fragment when lit or fogged, and
+    //    blending transparent ones over the opaque ones
+
+    let faces = object.face_indexes();
+    let opacities = object.face_opacities();
+    let fog = options.fog.as_ref();
+    let back_face_color = Rgb::from_srgb(BACK_FACE_COLOR);
+    let shading = object.shading();
+
+    // Transparent faces neither hide nor write depth, so they come after
+    // every opaque face, sorted back to front by the depth of their centers
+    // unless the blending doesn't depend on order. They are left out of
+    // depth only passes entirely, letting light and edges through.
+    let mut order: Vec<usize> = (0..faces.len()).filter(|&f| opacities[f] >= 1.0).collect();
+    let first_transparent = order.len();
+    if !depth_only {
+        let mut transparent: Vec<(usize, f32)> = (0..faces.len())
+            .filter(|&f| opacities[f] > 0.0 && opacities[f] < 1.0)
             .map(|f| {
-                f.vertices()
+                let face = &faces[f];
+                let center = face
                     .iter()
-                    .map(|&p| {
-                        // rotate then translate
-                        let rotated = rotate_point_about_origin_with_matrix(p, &rotation_matrix);
-                        rotated + position
-                    })
-                    .collect()
-            })
-            .map(|s: Vec<Point3>| {
-                // Let triangle ABC be defined by the points s[0], s[1], and s[2]
-                //
-                // 1. ABC has a surface normal N defined by the cross product of two of its legs,
-                //     N = AB X AC
-                // 2. ABC has a vector from the camera to its first vertex,
-                //     D = A - C
-                //    where C is the camera position.
-                //
-                // When D·N >= 0, the triangle should not be rendered.
-                //
-                // ref: https://en.wikipedia.org/wiki/Back-face_culling
-
-                let vec1 = s[1] - s[0]; // vector A-->B
-                let vec2 = s[2] - s[0]; // vector A-->C
-                let surface_normal = vec1.cross(vec2).normalize();
-                let dot = (s[0] - camera.position()).normalize().dot(surface_normal);
-
-                let orientation = if dot < 0.0 {
-                    SurfaceOrientation::TowardsCamera
-                } else {
-                    SurfaceOrientation::AwayFromCamera
-                };
-
-                Surface {
-                    vertices: s,
-                    camera_surface_dot: dot,
-                    orientation,
-                }
+                    .fold(Point3::default(), |sum, &i| sum + transformed.world[i])
+                    * (1.0 / face.len() as f32);
+                (f, camera.depth_of(center))
             })
             .collect();
 
-        // todo: handle z = 0, out of viewport, clipping z < 1, etc.
-        let mut triangles: Vec<Triangle> = Vec::new();
-        triangles.reserve(surfaces.len());
+        if options.transparency == Transparency::Sorted {
+            transparent.sort_by(|a, b| b.1.total_cmp(&a.1));
+        }
+        order.extend(transparent.iter().map(|&(f, _)| f));
+    }
+
+    let mut triangles: Vec<ProjectedTriangle> = Vec::with_capacity(faces.len());
+    let mut colors: Vec<Rgb> = Vec::with_capacity(faces.len());
+
+    // For lighting, the world space vertices, normal, and how directly the
+    // face faces the camera of each triangle. Clipping creates new vertices,
+    // so world positions are recovered from clip space.
+    let clip_to_world = camera.view_projection_matrix().inverse();
+    let mut lit_triangles: Vec<([Point3; 3], Point3, f32)> = Vec::new();
 
-        for s in surfaces.into_iter() {
-            if s.orientation == SurfaceOrientation::AwayFromCamera {
+    // For fog, the view depth of each triangle's vertices
+    let mut fog_depths: Vec<[f32; 3]> = Vec::new();
+
+    // For shading by vertex normals, the world space normal at each
+    // triangle's vertices, which clipping interpolates to new vertices
+    let smooth = shading.uses_vertex_normals();
+    let mut vertex_normals: Vec<[Point3; 3]> = Vec::new();
+
+    // Whether each triangle is a highlighted back face
+    let mut highlighted: Vec<bool> = Vec::with_capacity(faces.len());
+
+    // The opacity of each triangle, and the first transparent one
+    let mut triangle_opacities: Vec<f32> = Vec::with_capacity(faces.len());
+    let mut split: Option<usize> = None;
+
+    for (n, &f) in order.iter().enumerate() {
+        if n == first_transparent {
+            split = Some(triangles.len());
+        }
+        let face = &faces[f];
+        let mut normal = transformed.normals[f];
+
+        // Let triangle ABC be the first three vertices of the face.
+        //
+        // 1. ABC has a surface normal N defined by the cross product of two of its legs,
+        //     N = AB X AC
+        //    which is computed once when the object is loaded.
+        // 2. ABC has a viewing direction D towards its first vertex,
+        //     D = A - C
+        //    where C is the camera position, or the camera's forward
+        //    axis with orthographic projection.
+        //
+        // When D·N >= 0, the triangle faces away from the camera and should
+        // not be rendered, unless back faces are shown.
+        //
+        // ref: https://en.wikipedia.org/wiki/Back-face_culling
+        let mut dot = camera
+            .view_direction(transformed.world[face[0]])
+            .dot(normal);
+        let back_facing = dot >= 0.0;
+        if back_facing {
+            if options.back_faces == BackFaces::Cull {
                 continue;
             }
 
-            let projected_points: Vec<ProjectedPoint> = s
-                .vertices
-                .into_iter()
-                .map(|p| camera.project_point_with_depth(p))
-                .map(|p| projected_point_to_screen(p, (2, 2), screen.size()))
+            // Seen from behind, a face is shaded as if it were turned around
+            normal = -normal;
+            dot = -dot;
+        }
+        let highlight = back_facing && options.back_faces == BackFaces::Highlight;
+
+        let clip_points: Vec<Point4> = face.iter().map(|&i| transformed.clip[i]).collect();
+
+        let (clipped_points, clipped_normals) = if smooth {
+            let normals: Vec<Point3> = face
+                .iter()
+                .map(|&i| {
+                    if back_facing {
+                        -transformed.vertex_normals[i]
+                    } else {
+                        transformed.vertex_normals[i]
+                    }
+                })
                 .collect();
+            clip_polygon_with_normals(&clip_points, &normals)
+        } else {
+            (clip_polygon(&clip_points), Vec::new())
+        };
+        let projected_points: Vec<ProjectedPoint> = clipped_points
+            .iter()
+            .map(|&p| clip_to_screen(p, screen.raster_size()))
+            .collect();
 
-            triangles.push(Triangle {
-                projected: ProjectedTriangle {
-                    v0: projected_points[0].clone(),
-                    v1: projected_points[1].clone(),
-                    v2: projected_points[2].clone(),
-                },
-                color: make_gray_color(-s.camera_surface_dot, 0.0, 1.0),
+        // Fan out the clipped polygon into triangles. Unlit, faces are shaded
+        // as if by a light at the camera, which reflects straight back
+        let color = Rgb::gray(shading.shade(-dot, -dot));
+        let color = if highlight {
+            back_face_color * color
+        } else {
+            color
+        };
+        for i in 2..projected_points.len() {
+            triangles.push(ProjectedTriangle {
+                v0: projected_points[0].clone(),
+                v1: projected_points[i - 1].clone(),
+                v2: projected_points[i].clone(),
             });
+            colors.push(color);
+            highlighted.push(highlight);
+            triangle_opacities.push(opacities[f]);
+
+            if lighting.is_some() {
+                let world = |p: Point4| (clip_to_world * p).hom_to_euc();
+                lit_triangles.push((
+                    [
+                        world(clipped_points[0]),
+                        world(clipped_points[i - 1]),
+                        world(clipped_points[i]),
+                    ],
+                    normal,
+                    -dot,
+                ));
+            }
+
+            if smooth {
+                vertex_normals.push([
+                    clipped_normals[0],
+                    clipped_normals[i - 1],
+                    clipped_normals[i],
+                ]);
+            }
+
+            if fog.is_some() {
+                fog_depths.push([
+                    camera.view_depth(projected_points[0].z),
+                    camera.view_depth(projected_points[i - 1].z),
+                    camera.view_depth(projected_points[i].z),
+                ]);
+            }
+        }
+    }
+
+    if depth_only {
+        screen.fill_projected_triangles_depth(&triangles);
+        return;
+    }
+
+    let shader = |i: usize, weights: [f32; 3]| {
+        let color = match (shading, lighting) {
+            (Shading::Matcap(matcap), _) => {
+                let normals = &vertex_normals[i];
+                let normal =
+                    normals[0] * weights[0] + normals[1] * weights[1] + normals[2] * weights[2];
+                let color = matcap.color(camera.direction_to_view(normal.normalize()));
+                if highlighted[i] {
+                    back_face_color * color
+                } else {
+                    color
+                }
+            }
+            (_, Some(lighting)) => {
+                let (world, normal, facing) = &lit_triangles[i];
+                let p = world[0] * weights[0] + world[1] * weights[1] + world[2] * weights[2];
+                let intensity = lighting.intensity(p, *normal, *facing);
+                let specular = if shading.uses_specular() {
+                    lighting.specular(p, *normal, -camera.view_direction(p))
+                } else {
+                    0.0
+                };
+                let shade = shading.shade(intensity, specular);
+                if highlighted[i] {
+                    back_face_color * shade
+                } else {
+                    Rgb::gray(shade)
+                }
+            }
+            _ => colors[i],
+        };
+
+        match fog {
+            Some(fog) => {
+                // View depth is affine in world space, so it interpolates
+                // with the perspective corrected weights
+                let depths = &fog_depths[i];
+                let depth =
+                    depths[0] * weights[0] + depths[1] * weights[1] + depths[2] * weights[2];
+                fog.apply(color, depth)
+            }
+            None => color,
+        }
+    };
+
+    let split = split.unwrap_or(triangles.len());
+    let (opaque, transparent) = triangles.split_at(split);
+
+    if lighting.is_none() && fog.is_none() && !smooth {
+        screen.fill_projected_triangles(opaque, &colors[..split]);
+    } else {
+        screen.fill_projected_triangles_shaded(opaque, shader);
+    }
+
+    if !transparent.is_empty() {
+        let shader =
+            |i, weights| shader(split + i, weights).with_alpha(triangle_opacities[split + i]);
+        match options.transparency {
+            Transparency::Sorted => screen.fill_projected_triangles_blended(transparent, shader),
+            Transparency::Weighted => screen.fill_projected_triangles_weighted(transparent, shader),
+        }
+    }
+}
+
+// Draws every edge of the object once, including edges of faces pointing away
+// from the camera. Given a depth bias, edges are hidden by what is already in
+// the z buffer.
+fn render_wireframe(
+    object: &Object,
+    transformed: &TransformedObject,
+    screen: &mut ScreenBuffer,
+    color: Rgb,
+    style: &LineStyle,
+    depth_test: Option<DepthBias>,
+    depth_cue: Option<&DepthCue>,
+) {
+    for &(a, b) in object.edges() {
+        if let Some((a_c, b_c)) = clip_line(transformed.clip[a], transformed.clip[b]) {
+            let a_s = clip_to_screen(a_c, screen.raster_size());
+            let b_s = clip_to_screen(b_c, screen.raster_size());
+            screen.draw_styled_line(&a_s, &b_s, color, style, depth_test, depth_cue);
         }
+    }
+}
 
-        for triangle in triangles {
-            screen.fill_projected_triangle(&triangle.projected, triangle.color);
+// Draws the outline of an object over its surfaces when options has one:
+// silhouette edges between faces turned towards and away from the camera,
+// border edges of open surfaces, and crease edges between faces bent further
+// than the crease angle. Jumps in the z buffer are outlined too, to catch
+// what the edges miss.
+fn render_outline(
+    object: &Object,
+    transformed: &TransformedObject,
+    screen: &mut ScreenBuffer,
+    camera: &Camera,
+    options: &RenderOptions,
+    depth_cue: Option<&DepthCue>,
+) {
+    let outline = match &options.outline {
+        Some(outline) => outline,
+        None => return,
+    };
+
+    draw_depth_edges(screen, camera, outline);
+
+    // Whether each face is turned towards the camera, as in render_surfaces
+    let normals = &transformed.normals;
+    let front_facing: Vec<bool> = object
+        .face_indexes()
+        .iter()
+        .zip(normals.iter())
+        .map(|(face, &normal)| {
+            camera
+                .view_direction(transformed.world[face[0]])
+                .dot(normal)
+                < 0.0
+        })
+        .collect();
+    let crease_cos = outline.crease_angle.cos();
+
+    for (&(a, b), faces) in object.edges().iter().zip(object.edge_faces().iter()) {
+        let outlined = match faces[..] {
+            [f, g] => {
+                let silhouette = front_facing[f] != front_facing[g];
+                let crease =
+                    (front_facing[f] || front_facing[g]) && normals[f].dot(normals[g]) < crease_cos;
+                silhouette || crease
+            }
+            // Borders, and edges shared by more than two faces
+            _ => true,
+        };
+        if !outlined {
+            continue;
         }
 
-        if RENDER_DEBUG {
-            render_object_origin(position, screen, camera);
-            render_object_origin(Point3::default(), screen, camera);
+        if let Some((a_c, b_c)) = clip_line(transformed.clip[a], transformed.clip[b]) {
+            let a_s = clip_to_screen(a_c, screen.raster_size());
+            let b_s = clip_to_screen(b_c, screen.raster_size());
+            screen.draw_styled_line(
+                &a_s,
+                &b_s,
+                outline.color,
+                &options.line_style,
+                Some(DepthBias::new(camera)),
+                depth_cue,
+            );
         }
     }
 }
 
-fn render_raw_point(position: Point3, screen: &mut ScreenBuffer, camera: &Camera, color: u32) {
-    let z_space = camera.project_point(position);
-    let screen_space = projection_to_screen(z_space, (2, 2), screen.size());
-    screen.set_pixel_i(screen_space, color);
+fn render_raw_point(position: Point3, screen: &mut ScreenBuffer, camera: &Camera, color: Rgb) {
+    let clip_space = camera.project_to_clip(position);
+    if !in_depth_range(&clip_space) {
+        return;
+    }
+
+    let screen_space = clip_to_screen(clip_space, screen.raster_size());
+    screen.draw_point_depth(&screen_space, color, DepthBias::new(camera));
 }
 
-fn render_raw_line(p1: Point3, p2: Point3, screen: &mut ScreenBuffer, camera: &Camera, color: u32) {
-    let p1_s = projection_to_screen(camera.project_point(p1), (2, 2), screen.size());
-    let p2_s = projection_to_screen(camera.project_point(p2), (2, 2), screen.size());
-    screen.draw_line(p1_s, p2_s, color);
+fn render_raw_line(p1: Point3, p2: Point3, screen: &mut ScreenBuffer, camera: &Camera, color: Rgb) {
+    let clipped = clip_line(camera.project_to_clip(p1), camera.project_to_clip(p2));
+    if let Some((p1_c, p2_c)) = clipped {
+        let p1_s = clip_to_screen(p1_c, screen.raster_size());
+        let p2_s = clip_to_screen(p2_c, screen.raster_size());
+        let depth_test = Some(DepthBias::new(camera));
+        screen.draw_styled_line(&p1_s, &p2_s, color, &LineStyle::default(), depth_test, None);
+    }
 }
 
 fn render_object_origin(pos: Point3, screen: &mut ScreenBuffer, camera: &Camera) {
@@ -269,14 +992,8 @@ fn render_object_origin(pos: Point3, screen: &mut ScreenBuffer, camera: &Camera)
     let ry = pos + (0.0, 0.5, 0.0);
     let rz = pos + (0.0, 0.0, 0.5);
 
-    render_raw_line(pos, rx, screen, camera, 0xff0000);
-    render_raw_line(pos, ry, screen, camera, 0x00ff00);
-    render_raw_line(pos, rz, screen, camera, 0x0000ff);
-    render_raw_point(pos, screen, camera, 0x000000);
-}
-
-fn make_gray_color(intensity: f32, min: f32, max: f32) -> u32 {
-    let scaled = intensity * (max - min) + min;
-    let c = (scaled * 255.0) as u32;
-    (c << 16) | (c << 8) | c
+    render_raw_line(pos, rx, screen, camera, Rgb::new(1.0, 0.0, 0.0));
+    render_raw_line(pos, ry, screen, camera, Rgb::new(0.0, 1.0, 0.0));
+    render_raw_line(pos, rz, screen, camera, Rgb::new(0.0, 0.0, 1.0));
+    render_raw_point(pos, screen, camera, Rgb::BLACK);
 }
@@ -1,4 +1,8 @@
-use crate::screen_buffer::ScreenBuffer;
+use crate::ambient_occlusion::{apply_ambient_occlusion, AmbientOcclusion};
+use crate::color::Rgb;
+use crate::hdr::{apply_bloom, apply_tone_mapping, Bloom, ToneMapping};
+use crate::post_process::{apply_effects, Effect};
+use crate::screen_buffer::{AntiAliasing, ResolveFilter, ScreenBuffer};
 use crate::world::camera::Camera;
 use minifb::{Key, Window, WindowOptions};
 
@@ -9,7 +13,7 @@ pub trait Renderer<S> {
 pub struct Scene<T, S, F>
 where
     T: Renderer<S>,
-    F: Fn(&ScreenBuffer, &Window, &mut Camera, std::time::Duration) -> S,
+    F: FnMut(&ScreenBuffer, &Window, &mut Camera, std::time::Duration) -> S,
     S: Default + Copy + PartialEq,
 {
     screen: ScreenBuffer,
@@ -19,7 +23,12 @@ where
     last_frame: std::time::Instant,
     frame_time: std::time::Duration,
     camera: Camera,
-    background_color: u32,
+    background_color: Rgb,
+    ambient_occlusion: Option<AmbientOcclusion>,
+    bloom: Option<Bloom>,
+    tone_mapping: ToneMapping,
+    effects: Vec<Box<dyn Effect>>, // post-processing, applied in order
+    hdr_frame: Vec<Rgb>,           // the last exported frame, as resolved
 
     update_func: F,
     last_state: Option<S>,
@@ -28,22 +37,68 @@ where
 impl<T, S, F> Scene<T, S, F>
 where
     T: Renderer<S>,
-    F: Fn(&ScreenBuffer, &Window, &mut Camera, std::time::Duration) -> S,
+    F: FnMut(&ScreenBuffer, &Window, &mut Camera, std::time::Duration) -> S,
     S: Default + Copy + PartialEq,
 {
-    fn draw_frame(&mut self, state: S) {
+    // Scene-referred passes, which work on light that may be brighter than
+    // white, run before tone mapping, and display-referred effects after it
+    fn draw_frame(&mut self, state: S, keep_hdr_frame: bool) {
         self.screen.clear(self.background_color);
         self.object.render(&mut self.screen, &self.camera, state);
+        if let Some(ambient_occlusion) = &self.ambient_occlusion {
+            apply_ambient_occlusion(&mut self.screen, &self.camera, ambient_occlusion);
+        }
+        self.screen.resolve();
+        if keep_hdr_frame {
+            self.hdr_frame.clear();
+            self.hdr_frame.extend_from_slice(self.screen.pixels());
+        }
+        if let Some(bloom) = &self.bloom {
+            apply_bloom(&mut self.screen, bloom);
+        }
+        apply_tone_mapping(&mut self.screen, &self.tone_mapping);
+        apply_effects(&mut self.screen, &self.camera, &self.effects);
+        self.screen.encode();
         self.last_state = Some(state);
     }
 
     pub fn draw_and_export_frame(&mut self, state: S) -> &[u32] {
-        self.screen.clear(self.background_color);
-        self.object.render(&mut self.screen, &self.camera, state);
-        self.last_state = Some(state);
+        self.draw_frame(state, true);
         self.screen.buffer()
     }
 
+    // Colors of the last exported frame in linear light, as rendered, before
+    // bloom, tone mapping and effects
+    pub fn hdr_frame(&self) -> &[Rgb] {
+        &self.hdr_frame
+    }
+
+    pub fn set_anti_aliasing(&mut self, anti_aliasing: AntiAliasing, filter: ResolveFilter) {
+        let (width, height) = self.screen.size();
+        self.screen = ScreenBuffer::with_anti_aliasing(width, height, anti_aliasing, filter);
+        self.last_state = None;
+    }
+
+    pub fn set_ambient_occlusion(&mut self, ambient_occlusion: Option<AmbientOcclusion>) {
+        self.ambient_occlusion = ambient_occlusion;
+        self.last_state = None;
+    }
+
+    pub fn set_bloom(&mut self, bloom: Option<Bloom>) {
+        self.bloom = bloom;
+        self.last_state = None;
+    }
+
+    pub fn set_tone_mapping(&mut self, tone_mapping: ToneMapping) {
+        self.tone_mapping = tone_mapping;
+        self.last_state = None;
+    }
+
+    pub fn set_effects(&mut self, effects: Vec<Box<dyn Effect>>) {
+        self.effects = effects;
+        self.last_state = None;
+    }
+
     pub fn run(&mut self) {
         // Set FPS
         self.window.limit_update_rate(Some(self.frame_time));
@@ -66,7 +121,7 @@ where
 
             // Only render if something has changed
             if state_changed || camera_changed {
-                self.draw_frame(new_state);
+                self.draw_frame(new_state, false);
             }
 
             // Render buffer to screen
@@ -86,7 +141,7 @@ where
         size: (usize, usize),
         fps: u64,
         camera: Camera,
-        background_color: u32,
+        background_color: Rgb,
         update_func: F,
     ) -> Scene<T, S, F> {
         let screen = ScreenBuffer::new(size.0, size.1);
@@ -100,6 +155,11 @@ where
             frame_time: std::time::Duration::from_micros(1_000_000 / fps),
             camera,
             background_color,
+            ambient_occlusion: None,
+            bloom: None,
+            tone_mapping: ToneMapping::default(),
+            effects: Vec::new(),
+            hdr_frame: Vec::new(),
             update_func,
             last_state: None,
         }
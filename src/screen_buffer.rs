@@ -1,37 +1,114 @@
 use core::f32;
 
+use crate::color::{Rgb, Rgba};
+use crate::world::camera::Camera;
 use crate::world::projection::ProjectedTriangle;
+use std::ops::Range;
+use std::sync::Mutex;
+use std::thread;
 
+// Depth of the far plane in normalized device coordinates
+pub const FAR_DEPTH: f32 = 1.0;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AntiAliasing {
+    None,
+    // Rasterizes and shades an N x N grid of samples per pixel
+    Supersample(usize),
+    // Rasterizes an N x N grid of samples per pixel, but shades each pixel
+    // once for all of its covered samples
+    Multisample(usize),
+}
+
+// How samples are filtered down to pixels when anti-aliasing
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ResolveFilter {
+    Box,  // average of the pixel's own samples
+    Tent, // weighted towards the pixel center, reaching into neighboring pixels
+}
+
+// The screen buffer holds a color and depth for every sample. Without
+// anti-aliasing there is one sample per pixel, otherwise the samples form a
+// grid N times larger than the screen on each axis, which all drawing happens
+// in. Positions in this grid are called raster coordinates.
+//
+// Colors are kept in linear light, where they may be brighter than white, and
+// are only tone mapped and encoded to 8-bit sRGB for output.
 pub struct ScreenBuffer {
-    buffer: Vec<u32>,
+    buffer: Vec<Rgb>,
     z_buffer: Vec<f32>,
+    resolved: Vec<Rgb>, // pixel colors filtered down from the samples
+    output: Vec<u32>,   // pixel colors encoded in sRGB
     width: usize,
     height: usize,
+    raster_width: usize,
+    raster_height: usize,
+    anti_aliasing: AntiAliasing,
+    filter: ResolveFilter,
+    rasterizer: Rasterizer,
 }
 
 impl ScreenBuffer {
     pub fn new(width: usize, height: usize) -> ScreenBuffer {
+        ScreenBuffer::with_anti_aliasing(width, height, AntiAliasing::None, ResolveFilter::Box)
+    }
+
+    pub fn with_anti_aliasing(
+        width: usize,
+        height: usize,
+        anti_aliasing: AntiAliasing,
+        filter: ResolveFilter,
+    ) -> ScreenBuffer {
+        let factor = match anti_aliasing {
+            AntiAliasing::None => 1,
+            AntiAliasing::Supersample(n) | AntiAliasing::Multisample(n) => n.max(1),
+        };
+        let raster_width = width * factor;
+        let raster_height = height * factor;
+
         ScreenBuffer {
-            buffer: vec![0; width * height],
-            z_buffer: vec![0.0; width * height],
+            buffer: vec![Rgb::BLACK; raster_width * raster_height],
+            z_buffer: vec![0.0; raster_width * raster_height],
+            resolved: if factor > 1 {
+                vec![Rgb::BLACK; width * height]
+            } else {
+                Vec::new()
+            },
+            output: vec![0; width * height],
             width,
             height,
+            raster_width,
+            raster_height,
+            anti_aliasing,
+            filter,
+            rasterizer: Rasterizer::new(),
         }
     }
 
-    pub fn get_coords(&mut self, x: usize, y: usize) -> Option<&mut u32> {
-        if x >= self.width || y >= self.height {
+    pub fn get_coords(&mut self, x: usize, y: usize) -> Option<&mut Rgb> {
+        if x >= self.raster_width || y >= self.raster_height {
             None
         } else {
-            self.buffer.get_mut(y * self.width + x)
+            self.buffer.get_mut(y * self.raster_width + x)
         }
     }
 
-    pub fn get_pixel(&mut self, pixel: (usize, usize)) -> Option<&mut u32> {
+    pub fn get_pixel(&mut self, pixel: (usize, usize)) -> Option<&mut Rgb> {
         self.get_coords(pixel.0, pixel.1)
     }
 
-    pub fn set_pixel(&mut self, pixel: (usize, usize), value: u32) -> bool {
+    // Colors and depths of every sample in raster order, for passes that
+    // post-process a drawn frame
+    pub fn samples_mut(&mut self) -> (&mut [Rgb], &[f32]) {
+        (&mut self.buffer, &self.z_buffer)
+    }
+
+    // Depth in the z buffer at a sample, in raster coordinates
+    pub fn get_depth(&self, pixel: (isize, isize)) -> Option<f32> {
+        self.pixel_index(pixel).map(|index| self.z_buffer[index])
+    }
+
+    pub fn set_pixel(&mut self, pixel: (usize, usize), value: Rgb) -> bool {
         if let Some(p) = self.get_pixel(pixel) {
             *p = value;
             true
@@ -40,7 +117,7 @@ impl ScreenBuffer {
         }
     }
 
-    pub fn set_pixel_i(&mut self, pixel: (isize, isize), value: u32) -> bool {
+    pub fn set_pixel_i(&mut self, pixel: (isize, isize), value: Rgb) -> bool {
         if pixel.0 < 0 || pixel.1 < 0 {
             false
         } else {
@@ -48,9 +125,47 @@ impl ScreenBuffer {
         }
     }
 
-    pub fn clear(&mut self, color: u32) {
+    fn pixel_index(&self, pixel: (isize, isize)) -> Option<usize> {
+        if self.inside_screen(pixel) {
+            Some(pixel.1 as usize * self.raster_width + pixel.0 as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Sets a pixel if depth z passes the z buffer test. The z buffer itself
+    /// is left untouched.
+    pub fn set_pixel_depth_tested(&mut self, pixel: (isize, isize), z: f32, value: Rgb) -> bool {
+        match self.pixel_index(pixel) {
+            Some(index) if z < self.z_buffer[index] => {
+                self.buffer[index] = value;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Blends a color over a pixel by its opacity. When z is given, the pixel
+    /// is only blended if z passes the z buffer test.
+    pub fn blend_pixel(&mut self, pixel: (isize, isize), z: Option<f32>, value: Rgba) -> bool {
+        let index = match self.pixel_index(pixel) {
+            Some(index) => index,
+            None => return false,
+        };
+
+        if let Some(z) = z {
+            if z >= self.z_buffer[index] {
+                return false;
+            }
+        }
+
+        self.buffer[index] = value.over(self.buffer[index]);
+        true
+    }
+
+    pub fn clear(&mut self, color: Rgb) {
         self.buffer.fill(color);
-        self.z_buffer.fill(f32::MAX);
+        self.z_buffer.fill(FAR_DEPTH);
     }
 
     pub fn width(&self) -> usize {
@@ -65,131 +180,1458 @@ impl ScreenBuffer {
         (self.width, self.height)
     }
 
+    pub fn raster_size(&self) -> (usize, usize) {
+        (self.raster_width, self.raster_height)
+    }
+
+    // Number of samples per pixel along each axis
+    pub fn sample_factor(&self) -> usize {
+        self.raster_width / self.width.max(1)
+    }
+
+    // Pixel colors in 8-bit sRGB, ready to be shown, as of the last encode
     pub fn buffer(&self) -> &[u32] {
-        &self.buffer
+        &self.output
+    }
+
+    // Pixel colors in linear light, filtered down from the samples by resolve
+    pub fn pixels(&self) -> &[Rgb] {
+        if self.sample_factor() == 1 {
+            &self.buffer
+        } else {
+            &self.resolved
+        }
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [Rgb] {
+        if self.sample_factor() == 1 {
+            &mut self.buffer
+        } else {
+            &mut self.resolved
+        }
+    }
+
+    // Depth of every pixel, which is the depth of its closest sample when
+    // anti-aliasing
+    pub fn pixel_depths(&self) -> Vec<f32> {
+        let factor = self.sample_factor();
+        if factor == 1 {
+            return self.z_buffer.clone();
+        }
+
+        let mut depths = vec![FAR_DEPTH; self.width * self.height];
+        for (y, row) in self.z_buffer.chunks(self.raster_width).enumerate() {
+            let pixel_row = &mut depths[(y / factor) * self.width..(y / factor + 1) * self.width];
+            for (x, &z) in row.iter().enumerate() {
+                let depth = &mut pixel_row[x / factor];
+                *depth = depth.min(z);
+            }
+        }
+        depths
     }
 
     pub fn inside_screen(&self, p: (isize, isize)) -> bool {
-        (0 < p.0 && p.0 < (self.width as isize)) // inside x
-            && (0 < p.1 && p.1 < (self.height as isize)) // inside y
+        (0 <= p.0 && p.0 < (self.raster_width as isize)) // inside x
+            && (0 <= p.1 && p.1 < (self.raster_height as isize)) // inside y
     }
 
-    pub fn outside_screen(&self, p: (isize, isize)) -> bool {
-        !self.inside_screen(p)
+    /// Filters the samples down to one color per pixel, for pixels. Must be
+    /// called after drawing a frame, before any pass that works on pixels.
+    pub fn resolve(&mut self) {
+        let factor = self.sample_factor();
+        if factor > 1 {
+            self.filter_samples(factor);
+        }
     }
 
-    /// Fills a projected triangle onto the screen buffer. This method exists
-    /// here to have optimized, unchecked access into the buffer and z buffer.
-    pub fn fill_projected_triangle(&mut self, triangle: &ProjectedTriangle, color: u32) {
-        // Extract vertices
-        let v0 = &triangle.v0;
-        let v1 = &triangle.v1;
-        let v2 = &triangle.v2;
+    /// Encodes the pixel colors in sRGB for buffer, clamping them to white.
+    /// Must be called after the last pass over a frame's pixels, once they
+    /// have been tone mapped.
+    pub fn encode(&mut self) {
+        let pixels = if self.sample_factor() == 1 {
+            &self.buffer
+        } else {
+            &self.resolved
+        };
+        for (out, color) in self.output.iter_mut().zip(pixels.iter()) {
+            *out = color.to_srgb();
+        }
+    }
 
-        // Find bounding box (clamped to screen boundaries)
-        let min_x = v0.x.min(v1.x).min(v2.x).max(0.0) as i32;
-        let min_y = v0.y.min(v1.y).min(v2.y).max(0.0) as i32;
-        let max_x = v0.x.max(v1.x).max(v2.x).min(self.width as f32 - 1.0) as i32;
-        let max_y = v0.y.max(v1.y).max(v2.y).min(self.height as f32 - 1.0) as i32;
+    fn filter_samples(&mut self, factor: usize) {
+        // The filter reaches this far from the pixel center, in samples
+        let (radius, tent) = match self.filter {
+            ResolveFilter::Box => (factor as isize / 2, false),
+            ResolveFilter::Tent => (factor as isize, true),
+        };
+        let odd = factor % 2 == 1;
 
-        // Calculate triangle area (to determine winding order)
-        let area = 0.5 * ((v1.x - v0.x) * (v2.y - v0.y) - (v2.x - v0.x) * (v1.y - v0.y));
+        for y in 0..self.height {
+            for x in 0..self.width {
+                // Center of the pixel in raster coordinates
+                let center_x = ((x * factor) as f32) + factor as f32 / 2.0;
+                let center_y = ((y * factor) as f32) + factor as f32 / 2.0;
+                let first_x = (x * factor) as isize + factor as isize / 2 - radius;
+                let first_y = (y * factor) as isize + factor as isize / 2 - radius;
+                let last_offset = 2 * radius + if odd { 1 } else { 0 };
 
-        // Skip degenerate triangles
-        if area.abs() < 1e-6 {
+                let mut total = Rgb::BLACK;
+                let mut total_weight = 0.0;
+
+                for sy in first_y..first_y + last_offset {
+                    for sx in first_x..first_x + last_offset {
+                        let index = match self.pixel_index((sx, sy)) {
+                            Some(index) => index,
+                            None => continue,
+                        };
+
+                        let weight = if tent {
+                            let dx = (sx as f32 + 0.5 - center_x).abs() / factor as f32;
+                            let dy = (sy as f32 + 0.5 - center_y).abs() / factor as f32;
+                            (1.0 - dx).max(0.0) * (1.0 - dy).max(0.0)
+                        } else {
+                            1.0
+                        };
+
+                        total += self.buffer[index] * weight;
+                        total_weight += weight;
+                    }
+                }
+
+                self.resolved[y * self.width + x] = total * (1.0 / total_weight);
+            }
+        }
+    }
+
+    // Samples are shaded in blocks of this size along each axis
+    fn shading_block(&self) -> usize {
+        match self.anti_aliasing {
+            AntiAliasing::Multisample(_) => self.sample_factor(),
+            _ => 1,
+        }
+    }
+
+    /// Fills projected triangles onto the screen buffer, each in its own
+    /// color. This method exists here to have optimized, unchecked access
+    /// into the buffer and z buffer.
+    pub fn fill_projected_triangles(&mut self, triangles: &[ProjectedTriangle], colors: &[Rgb]) {
+        let block = self.shading_block();
+
+        self.raster_triangles(
+            triangles,
+            block,
+            DepthTest::Write,
+            |triangle, samples, buffer| {
+                for sample in samples {
+                    buffer[sample.index] = colors[triangle];
+                }
+            },
+        );
+    }
+
+    /// Fills projected triangles onto the screen buffer, coloring each
+    /// fragment with shader. The shader is given the index of the triangle
+    /// and the perspective-correct weights of its vertices at the fragment,
+    /// and is called once per pixel when multisampling.
+    pub fn fill_projected_triangles_shaded<F>(&mut self, triangles: &[ProjectedTriangle], shader: F)
+    where
+        F: Fn(usize, [f32; 3]) -> Rgb + Sync,
+    {
+        let block = self.shading_block();
+
+        self.raster_triangles(
+            triangles,
+            block,
+            DepthTest::Write,
+            |triangle, samples, buffer| {
+                // Shade once, at the first sample that passed the z test
+                let weights = perspective_weights(&triangles[triangle], samples[0].weights);
+                let color = shader(triangle, weights);
+
+                for sample in samples {
+                    buffer[sample.index] = color;
+                }
+            },
+        );
+    }
+
+    /// Fills projected triangles into the z buffer only, leaving the pixel
+    /// buffer untouched. Used as a depth pre-pass for later depth-tested
+    /// drawing.
+    pub fn fill_projected_triangles_depth(&mut self, triangles: &[ProjectedTriangle]) {
+        self.raster_triangles(triangles, 1, DepthTest::Write, |_, _, _| {});
+    }
+
+    /// Blends projected triangles over the screen buffer in the order given,
+    /// coloring fragments with shader like fill_projected_triangles_shaded,
+    /// and blending them by the opacity of the shaded color. Blended fragments
+    /// are hidden by the z buffer but don't write to it, so that transparent
+    /// triangles never hide each other; they should be given back to front.
+    pub fn fill_projected_triangles_blended<F>(
+        &mut self,
+        triangles: &[ProjectedTriangle],
+        shader: F,
+    ) where
+        F: Fn(usize, [f32; 3]) -> Rgba + Sync,
+    {
+        let block = self.shading_block();
+
+        self.raster_triangles(
+            triangles,
+            block,
+            DepthTest::Keep,
+            |triangle, samples, buffer| {
+                let weights = perspective_weights(&triangles[triangle], samples[0].weights);
+                let color = shader(triangle, weights);
+
+                for sample in samples {
+                    buffer[sample.index] = color.over(buffer[sample.index]);
+                }
+            },
+        );
+    }
+
+    /// Blends projected triangles over the screen buffer in any order,
+    /// coloring fragments with shader like fill_projected_triangles_blended.
+    /// Like fill_projected_triangles_blended, the z buffer hides fragments but
+    /// isn't written to.
+    ///
+    /// Instead of blending one over another, the fragments covering a sample
+    /// are averaged, weighted by opacity and closeness to the camera, and the
+    /// average is blended over by how much light all of them block together.
+    /// This approximates sorted blending without sorting, and without popping
+    /// when triangles cross (weighted blended order-independent transparency).
+    ///
+    /// ref: https://jcgt.org/published/0002/02/09/
+    pub fn fill_projected_triangles_weighted<F>(
+        &mut self,
+        triangles: &[ProjectedTriangle],
+        shader: F,
+    ) where
+        F: Fn(usize, [f32; 3]) -> Rgba + Sync,
+    {
+        let block = self.shading_block();
+
+        self.raster_triangles_with(
+            triangles,
+            block,
+            DepthTest::Keep,
+            |samples| vec![WeightedSample::default(); samples],
+            |triangle, samples, _, accumulated| {
+                let weights = perspective_weights(&triangles[triangle], samples[0].weights);
+                let color = shader(triangle, weights);
+                let opacity = color.a;
+
+                for sample in samples {
+                    // Closer fragments weigh more, following equation 10 of
+                    // the reference for depth in [0, 1]
+                    let weight = opacity * (3e3 * (1.0 - sample.z).powi(3)).max(1e-2);
+
+                    let target = &mut accumulated[sample.index];
+                    target.color += color.rgb() * (opacity * weight);
+                    target.opacity += opacity * weight;
+                    target.revealage *= 1.0 - opacity;
+                }
+            },
+            |buffer, accumulated| {
+                for (dst, sample) in buffer.iter_mut().zip(accumulated.iter()) {
+                    if sample.revealage < 1.0 {
+                        let average = sample.color * (1.0 / sample.opacity.max(1e-5));
+                        *dst = average.with_alpha(1.0 - sample.revealage).over(*dst);
+                    }
+                }
+            },
+        );
+    }
+
+    // Rasterizes triangles in submission order. With several threads, the
+    // screen is split into tiles, each triangle is binned into the tiles its
+    // bounding box overlaps, and worker threads take tiles from a shared
+    // queue. A tile is drawn into its own copy of the buffers, which is
+    // copied back once all tiles are done. A single thread draws straight
+    // into the buffers instead, as one tile covering the whole screen.
+    //
+    // Every tile receives its triangles in submission order, and a sample is
+    // only ever touched by the tile containing it, so the result is the same
+    // bit for bit as drawing the triangles one after another.
+    //
+    // Covered samples are tested against the z buffer as they are found, and
+    // depth_test decides whether the samples passing write their depth.
+    // fragment is called with the index of the triangle, the samples that
+    // passed indexed into the tile, at least one of them, and the tile's
+    // color buffer.
+    fn raster_triangles<F>(
+        &mut self,
+        triangles: &[ProjectedTriangle],
+        block: usize,
+        depth_test: DepthTest,
+        fragment: F,
+    ) where
+        F: Fn(usize, &[Sample], &mut [Rgb]) + Sync,
+    {
+        self.raster_triangles_with(
+            triangles,
+            block,
+            depth_test,
+            |_| (),
+            |triangle, samples, buffer, _| fragment(triangle, samples, buffer),
+            |_, _| {},
+        );
+    }
+
+    // Rasterizes triangles like raster_triangles, giving each tile scratch
+    // data of its own. The scratch data is made by init from the number of
+    // samples in the tile, passed to every fragment of the tile, and finally
+    // given to finish along with the tile's buffer once all of the tile's
+    // triangles are drawn.
+    fn raster_triangles_with<T, I, F, R>(
+        &mut self,
+        triangles: &[ProjectedTriangle],
+        block: usize,
+        depth_test: DepthTest,
+        init: I,
+        fragment: F,
+        finish: R,
+    ) where
+        I: Fn(usize) -> T + Sync,
+        F: Fn(usize, &[Sample], &mut [Rgb], &mut T) + Sync,
+        R: Fn(&mut [Rgb], &T) + Sync,
+    {
+        let (width, height) = (self.raster_width, self.raster_height);
+        let kernel = self.rasterizer.kernel;
+
+        if !self.rasterizer.tiled {
+            let rect = TileRect {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            };
+            let (buffer, z_buffer) = (&mut self.buffer, &mut self.z_buffer);
+            let mut blocks = BlockScratch::default();
+            let mut scratch = init(buffer.len());
+            for (i, triangle) in triangles.iter().enumerate() {
+                if let Some(setup) = TriangleSetup::new(triangle, width, height) {
+                    let target = RasterTarget {
+                        rect: &rect,
+                        z_buffer,
+                        kernel,
+                        depth_test,
+                    };
+                    raster_triangle(&setup, target, block, &mut blocks, |samples| {
+                        fragment(i, samples, buffer, &mut scratch)
+                    });
+                }
+            }
+            finish(buffer, &scratch);
             return;
         }
 
-        // We'll define edge functions in a consistent manner:
-        // For each edge (v_i -> v_i+1), we want:
-        // - Points to the LEFT of the edge to be positive
-        // - Points to the RIGHT of the edge to be negative
-        //
-        // Edge function: E(x,y) = (x - x_i) * (y_i+1 - y_i) - (y - y_i) * (x_i+1 - x_i)
+        // Tiles are aligned to shading blocks so that no block is split
+        let tile_size = TILE_SIZE.div_ceil(block) * block;
+        let tiles_x = width.div_ceil(tile_size);
+        let tiles_y = height.div_ceil(tile_size);
+
+        let mut tiles: Vec<Tile> = Vec::with_capacity(tiles_x * tiles_y);
+        for tile_y in 0..tiles_y {
+            for tile_x in 0..tiles_x {
+                let x = tile_x * tile_size;
+                let y = tile_y * tile_size;
+                tiles.push(Tile::new(
+                    x,
+                    y,
+                    tile_size.min(width - x),
+                    tile_size.min(height - y),
+                ));
+            }
+        }
+
+        // Set up the triangles and bin them by their bounding boxes. All bins
+        // share one list, tile after tile, so the triangles are counted per
+        // tile first to find where each tile's bin starts.
+        let setups: Vec<Option<TriangleSetup>> = triangles
+            .iter()
+            .map(|t| TriangleSetup::new(t, width, height))
+            .collect();
+        let covered_tiles = |setup: &TriangleSetup| {
+            let tiles_across = setup.min_x / tile_size..=setup.max_x / tile_size;
+            (setup.min_y / tile_size..=setup.max_y / tile_size).flat_map(move |tile_y| {
+                tiles_across
+                    .clone()
+                    .map(move |tile_x| tile_y * tiles_x + tile_x)
+            })
+        };
+
+        let mut bin_ends = vec![0; tiles.len()];
+        for setup in setups.iter().flatten() {
+            for tile in covered_tiles(setup) {
+                bin_ends[tile] += 1;
+            }
+        }
+        let mut binned = 0;
+        for (tile, end) in tiles.iter_mut().zip(bin_ends.iter_mut()) {
+            tile.triangles = binned..binned + *end;
+            binned += *end;
+            *end = tile.triangles.start;
+        }
+
+        let mut bins: Vec<u32> = vec![0; binned];
+        for (i, setup) in setups.iter().enumerate() {
+            if let Some(setup) = setup {
+                for tile in covered_tiles(setup) {
+                    bins[bin_ends[tile]] = i as u32;
+                    bin_ends[tile] += 1;
+                }
+            }
+        }
+
+        let threads = self.rasterizer.threads.min(tiles.len());
+
+        {
+            let buffer = &self.buffer;
+            let z_buffer = &self.z_buffer;
+            let setups = &setups;
+            let bins = &bins;
+            let init = &init;
+            let fragment = &fragment;
+            let finish = &finish;
+            let queue = Mutex::new(tiles.iter_mut().filter(|t| !t.triangles.is_empty()));
+
+            let work = || {
+                // Each worker reuses its scratch space for every triangle
+                let mut blocks = BlockScratch::default();
+
+                loop {
+                    let tile = match queue.lock().unwrap().next() {
+                        Some(tile) => tile,
+                        None => break,
+                    };
+
+                    tile.load(buffer, z_buffer, width);
+                    let Tile {
+                        rect,
+                        buffer: tile_buffer,
+                        z_buffer: tile_z_buffer,
+                        triangles: tile_triangles,
+                    } = tile;
+
+                    let mut scratch = init(tile_buffer.len());
+                    for &i in bins[tile_triangles.clone()].iter() {
+                        let i = i as usize;
+                        let setup = setups[i].as_ref().unwrap();
+                        let target = RasterTarget {
+                            rect,
+                            z_buffer: tile_z_buffer,
+                            kernel,
+                            depth_test,
+                        };
+                        raster_triangle(setup, target, block, &mut blocks, |samples| {
+                            fragment(i, samples, tile_buffer, &mut scratch)
+                        });
+                    }
+                    finish(tile_buffer, &scratch);
+                }
+            };
+
+            thread::scope(|scope| {
+                for _ in 1..threads {
+                    scope.spawn(work);
+                }
+                work();
+            });
+        }
+
+        for tile in tiles.iter().filter(|t| !t.triangles.is_empty()) {
+            tile.store(&mut self.buffer, &mut self.z_buffer, width);
+        }
+    }
+}
+
+// How triangles are rasterized, chosen for the machine a buffer is made on
+#[derive(Copy, Clone, Debug)]
+struct Rasterizer {
+    threads: usize,     // worker threads drawing tiles in parallel
+    tiled: bool,        // whether the screen is split into tiles
+    kernel: SpanKernel, // how rows of samples are tested
+}
+
+impl Rasterizer {
+    fn new() -> Rasterizer {
+        let threads = thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+
+        // Tiles only pay for binning and copying them when drawn in parallel
+        Rasterizer {
+            threads,
+            tiled: threads > 1,
+            kernel: SpanKernel::detect(),
+        }
+    }
+}
+
+// How spans of samples in a row are tested for coverage and depth. Every
+// kernel finds the same samples with the same depths and weights, bit for
+// bit, so the choice only changes how fast triangles are drawn.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum SpanKernel {
+    Scalar,
+    #[cfg(target_arch = "x86_64")]
+    Avx2, // eight samples at a time
+}
+
+impl SpanKernel {
+    // Picks the fastest kernel the CPU supports
+    fn detect() -> SpanKernel {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx2") {
+                return SpanKernel::Avx2;
+            }
+        }
+
+        SpanKernel::Scalar
+    }
+}
+
+// Whether samples passing the depth test write their depth to the z buffer
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum DepthTest {
+    Write, // opaque surfaces, hiding whatever is drawn behind them later
+    Keep,  // transparent surfaces, which are hidden but never hide
+}
+
+// Size of a screen tile along each axis, in samples
+const TILE_SIZE: usize = 64;
+
+// Transparent fragments covering a sample, accumulated for weighted blended
+// order-independent transparency
+#[derive(Copy, Clone)]
+struct WeightedSample {
+    color: Rgb,     // sum of colors weighted by opacity and weight
+    opacity: f32,   // sum of opacities weighted by weight
+    revealage: f32, // fraction of the background showing through all fragments
+}
+
+impl Default for WeightedSample {
+    fn default() -> Self {
+        WeightedSample {
+            color: Rgb::BLACK,
+            opacity: 0.0,
+            revealage: 1.0,
+        }
+    }
+}
+
+// A rectangle of the screen in samples
+#[derive(Copy, Clone)]
+struct TileRect {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+// A part of the screen with its own copy of the buffers, and where the
+// indexes of the triangles that may cover it are in the bins
+struct Tile {
+    rect: TileRect,
+    buffer: Vec<Rgb>,
+    z_buffer: Vec<f32>,
+    triangles: Range<usize>,
+}
+
+impl Tile {
+    fn new(x: usize, y: usize, width: usize, height: usize) -> Tile {
+        Tile {
+            rect: TileRect {
+                x,
+                y,
+                width,
+                height,
+            },
+            buffer: Vec::new(),
+            z_buffer: Vec::new(),
+            triangles: 0..0,
+        }
+    }
+
+    // Copies the tile's part of the screen buffers into the tile
+    fn load(&mut self, buffer: &[Rgb], z_buffer: &[f32], screen_width: usize) {
+        let rect = self.rect;
+        self.buffer.clear();
+        self.z_buffer.clear();
+        for y in rect.y..rect.y + rect.height {
+            let row = y * screen_width + rect.x;
+            self.buffer
+                .extend_from_slice(&buffer[row..row + rect.width]);
+            self.z_buffer
+                .extend_from_slice(&z_buffer[row..row + rect.width]);
+        }
+    }
+
+    // Copies the tile back into its part of the screen buffers
+    fn store(&self, buffer: &mut [Rgb], z_buffer: &mut [f32], screen_width: usize) {
+        let rect = self.rect;
+        for (i, y) in (rect.y..rect.y + rect.height).enumerate() {
+            let row = y * screen_width + rect.x;
+            let tile_row = i * rect.width;
+            buffer[row..row + rect.width]
+                .copy_from_slice(&self.buffer[tile_row..tile_row + rect.width]);
+            z_buffer[row..row + rect.width]
+                .copy_from_slice(&self.z_buffer[tile_row..tile_row + rect.width]);
+        }
+    }
+}
+
+// Takes normalized depths from a z buffer back to view space depths, as seen
+// by the camera they were drawn with. Depths nothing was drawn to are
+// infinitely far away.
+pub fn view_depths(z_buffer: &[f32], camera: &Camera) -> Vec<f32> {
+    z_buffer
+        .iter()
+        .map(|&z| {
+            if z < FAR_DEPTH {
+                camera.view_depth(z)
+            } else {
+                f32::INFINITY
+            }
+        })
+        .collect()
+}
+
+// Corrects screen space barycentric weights for perspective. Attributes vary
+// linearly across a triangle in world space, but not on screen, where each
+// vertex's influence shrinks with its distance from the camera: weighting by
+// 1 / w and normalizing gives the weights in world space.
+fn perspective_weights(triangle: &ProjectedTriangle, weights: [f32; 3]) -> [f32; 3] {
+    let b0 = weights[0] / triangle.v0.w;
+    let b1 = weights[1] / triangle.v1.w;
+    let b2 = weights[2] / triangle.v2.w;
+    let sum = b0 + b1 + b2;
+    [b0 / sum, b1 / sum, b2 / sum]
+}
+
+// Raster coordinates are snapped to fixed point with this many fractional
+// bits before rasterizing, so that edge functions are evaluated exactly
+const SUBPIXEL_BITS: u32 = 8;
+const SUBPIXEL_ONE: i64 = 1 << SUBPIXEL_BITS;
+const SUBPIXEL_HALF: i64 = SUBPIXEL_ONE / 2;
+
+// Rounds to the nearest step of the fixed point grid, halfway cases to even.
+// Adding and taking away 1.5 * 2^52 leaves no fractional bits in a double,
+// which rounds far cheaper than f32::round, a library call on CPUs without
+// SSE4.1. Raster coordinates are far too small for the trick to overflow.
+fn to_fixed(v: f32) -> i64 {
+    const ROUNDING: f64 = 6755399441055744.0;
+    ((v as f64 * SUBPIXEL_ONE as f64 + ROUNDING) - ROUNDING) as i64
+}
+
+// Edge function of the directed edge p-->q in fixed point:
+//
+//     E(x, y) = (p_y - q_y) * x + (q_x - p_x) * y + (p_x * q_y - q_x * p_y)
+//
+// E is positive to the left of the edge, which is the inside of a triangle
+// with positive area.
+struct Edge {
+    a: i64,
+    b: i64,
+    c: i64,
+    bias: i64,
+}
+
+impl Edge {
+    fn new(p: (i64, i64), q: (i64, i64)) -> Edge {
+        let a = p.1 - q.1;
+        let b = q.0 - p.0;
+        let c = p.0 * q.1 - q.0 * p.1;
+
+        // Top-left fill rule: a sample exactly on an edge belongs to the
+        // triangle only if the edge is a left edge or a horizontal top edge.
+        // The gradient (a, b) points inside, so it points right for left
+        // edges and down for top edges. Biasing the other edges by one makes
+        // E + bias >= 0 exclude their samples.
         //
-        // For a counter-clockwise triangle, a point is INSIDE when all edge functions are positive
-
-        // Edge functions: E(x, y) = (y_i - y_j) * x + (x_j - x_i) * y + (x_i * y_j - x_j * y_i)
-        // Edges: v0->v1, v1->v2, v2->v0
-        let edge0 = (
-            v0.y - v1.y,               // A
-            v1.x - v0.x,               // B
-            v0.x * v1.y - v1.x * v0.y, // C
-        );
+        // ref: https://learn.microsoft.com/en-us/windows/win32/direct3d11/d3d10-graphics-programming-guide-rasterizer-stage-rules
+        let is_top_left = a > 0 || (a == 0 && b > 0);
 
-        let edge1 = (v1.y - v2.y, v2.x - v1.x, v1.x * v2.y - v2.x * v1.y);
+        Edge {
+            a,
+            b,
+            c,
+            bias: is_top_left as i64 - 1,
+        }
+    }
 
-        let edge2 = (v2.y - v0.y, v0.x - v2.x, v2.x * v0.y - v0.x * v2.y);
+    fn eval(&self, x: i64, y: i64) -> i64 {
+        self.a * x + self.b * y + self.c
+    }
+}
 
-        // Step values for edge functions when moving in x or y direction
-        let step_x = [edge0.0, edge1.0, edge2.0];
-        let step_y = [edge0.1, edge1.1, edge2.1];
+// A triangle snapped to fixed point and wound so that its inside is to the
+// left of every edge, ready to be rasterized into any number of tiles
+struct TriangleSetup {
+    edges: [Edge; 3],
+    z: [f32; 3],
+    area_recip: f32,
+    swapped: bool, // whether v1 and v2 were swapped to wind the triangle
 
-        // Precompute starting position (top-left corner of bounding box)
-        let start_x = min_x as f32 + 0.5; // Center of pixel
-        let start_y = min_y as f32 + 0.5;
+    // Whether the edge functions change by little enough across a group of
+    // samples for the lanes of a group to be tested in 32 bits
+    lanes_fit: bool,
 
-        // Initialize edge function values for the first row
-        let mut row_edge_vals = [
-            edge0.0 * start_x + edge0.1 * start_y + edge0.2,
-            edge1.0 * start_x + edge1.1 * start_y + edge1.2,
-            edge2.0 * start_x + edge2.1 * start_y + edge2.2,
+    // Bounding box in samples, clamped to the screen
+    min_x: usize,
+    min_y: usize,
+    max_x: usize,
+    max_y: usize,
+}
+
+impl TriangleSetup {
+    // Returns None for triangles that are degenerate or entirely off screen
+    fn new(triangle: &ProjectedTriangle, width: usize, height: usize) -> Option<TriangleSetup> {
+        let v0 = &triangle.v0;
+        let mut v1 = &triangle.v1;
+        let mut v2 = &triangle.v2;
+
+        let p0 = (to_fixed(v0.x), to_fixed(v0.y));
+        let mut p1 = (to_fixed(v1.x), to_fixed(v1.y));
+        let mut p2 = (to_fixed(v2.x), to_fixed(v2.y));
+
+        // Find the bounding box of the sample centers inside the triangle's
+        // bounds (clamped to screen boundaries). Small triangles often fall
+        // between sample centers and cover nothing.
+        let first_center = |v: i64| (v - SUBPIXEL_HALF + SUBPIXEL_ONE - 1) >> SUBPIXEL_BITS;
+        let last_center = |v: i64| (v - SUBPIXEL_HALF) >> SUBPIXEL_BITS;
+        let min_x = first_center(p0.0.min(p1.0).min(p2.0));
+        let min_y = first_center(p0.1.min(p1.1).min(p2.1));
+        let max_x = last_center(p0.0.max(p1.0).max(p2.0));
+        let max_y = last_center(p0.1.max(p1.1).max(p2.1));
+
+        if min_x > max_x || min_y > max_y {
+            return None;
+        }
+        if max_x < 0 || max_y < 0 || min_x >= width as i64 || min_y >= height as i64 {
+            return None;
+        }
+
+        // Twice the signed area of the triangle, which is also E01 at v2
+        let mut area = (p1.0 - p0.0) * (p2.1 - p0.1) - (p2.0 - p0.0) * (p1.1 - p0.1);
+
+        // Skip degenerate triangles
+        if area == 0 {
+            return None;
+        }
+
+        // Wind the triangle so that its inside is to the left of every edge
+        let swapped = area < 0;
+        if swapped {
+            std::mem::swap(&mut v1, &mut v2);
+            std::mem::swap(&mut p1, &mut p2);
+            area = -area;
+        }
+
+        let edges = [
+            Edge::new(p0, p1), // v0 --> v1
+            Edge::new(p1, p2), // v1 --> v2
+            Edge::new(p2, p0), // v2 --> v0
         ];
+        let lanes_fit = edges
+            .iter()
+            .all(|edge| (edge.a << SUBPIXEL_BITS).abs() * (LANES as i64 - 1) <= i32::MAX as i64);
+
+        Some(TriangleSetup {
+            edges,
+            z: [v0.z, v1.z, v2.z],
+            area_recip: (area as f32).recip(),
+            swapped,
+            lanes_fit,
+            min_x: min_x.max(0) as usize,
+            min_y: min_y.max(0) as usize,
+            max_x: max_x.min(width as i64 - 1) as usize,
+            max_y: max_y.min(height as i64 - 1) as usize,
+        })
+    }
+
+    // Puts weights of the wound vertices back in the order of the original
+    // triangle's vertices
+    fn vertex_weights(&self, w0: f32, w1: f32, w2: f32) -> [f32; 3] {
+        if self.swapped {
+            [w0, w2, w1]
+        } else {
+            [w0, w1, w2]
+        }
+    }
+}
+
+// A sample covered by a triangle
+#[derive(Copy, Clone, Default)]
+struct Sample {
+    index: usize,      // index into the buffer being drawn to
+    z: f32,            // interpolated depth
+    weights: [f32; 3], // screen space barycentric weights of v0, v1 and v2
+}
+
+// Space for gathering the covered samples of a row of blocks, made once and
+// reused for every triangle: room for every sample of each block, one block
+// after another, and how many of each block's samples are covered
+#[derive(Default)]
+struct BlockScratch {
+    samples: Vec<Sample>,
+    lengths: Vec<usize>,
+}
+
+// The tile a triangle is rasterized into, with its z buffer, and how its
+// samples are tested against the z buffer
+struct RasterTarget<'a> {
+    rect: &'a TileRect,
+    z_buffer: &'a mut [f32],
+    kernel: SpanKernel,
+    depth_test: DepthTest,
+}
+
+// Rasterizes the part of a triangle inside of a tile. Covered samples that
+// pass the depth test are passed to fragment in blocks
+// of block x block samples, aligned to the sample grid, indexed into the tile.
+// Callers can then shade once per block.
+//
+// Vertices are snapped to a fixed point grid and samples are tested with the
+// top-left fill rule, so a sample on an edge shared by two triangles is
+// covered by exactly one of them.
+fn raster_triangle<F: FnMut(&[Sample])>(
+    triangle: &TriangleSetup,
+    target: RasterTarget,
+    block: usize,
+    scratch: &mut BlockScratch,
+    mut fragment: F,
+) {
+    let RasterTarget {
+        rect: tile,
+        z_buffer,
+        kernel,
+        depth_test,
+    } = target;
+
+    // Intersect the bounding box with the tile
+    let min_x = triangle.min_x.max(tile.x);
+    let min_y = triangle.min_y.max(tile.y);
+    let max_x = triangle.max_x.min(tile.x + tile.width - 1);
+    let max_y = triangle.max_y.min(tile.y + tile.height - 1);
+
+    if min_x > max_x || min_y > max_y {
+        return;
+    }
 
-        // Iterate over each pixel in the bounding box
+    // Without blocks to gather, every sample is a block of its own
+    if block == 1 {
+        let x_range = min_x..max_x + 1;
         for y in min_y..=max_y {
-            // Initialize edge values for this row
-            let mut edge_vals = row_edge_vals.clone();
-
-            for x in min_x..=max_x {
-                // Check if pixel is inside triangle
-                // A point is inside if it's on the left side of all edges
-                // With our edge function definition, "left" means positive values
-                let inside = edge_vals[0] >= 0.0 && edge_vals[1] >= 0.0 && edge_vals[2] >= 0.0;
-
-                if inside {
-                    // Calculate barycentric coordinates
-                    // For correct interpolation, make sure they're normalized
-                    let mut w = [0.0; 3];
-                    w[0] = edge_vals[1].abs() / (2.0 * area.abs()); // alpha (weight for v0)
-                    w[1] = edge_vals[2].abs() / (2.0 * area.abs()); // beta (weight for v1)
-                    w[2] = edge_vals[0].abs() / (2.0 * area.abs()); // gamma (weight for v2)
-
-                    // Normalize weights to ensure they sum to 1
-                    let sum = w[0] + w[1] + w[2];
-                    if sum > 1e-6 {
-                        w[0] /= sum;
-                        w[1] /= sum;
-                        w[2] /= sum;
-                    }
+            let row = (y - tile.y) * tile.width;
+            let depths = &mut z_buffer[row + x_range.start - tile.x..row + x_range.end - tile.x];
+            raster_span(
+                kernel,
+                triangle,
+                y,
+                x_range.clone(),
+                depths,
+                depth_test,
+                |mut sample| {
+                    sample.index = row + (sample.index - tile.x);
+                    fragment(std::slice::from_ref(&sample));
+                },
+            );
+        }
+        return;
+    }
 
-                    // Perspective-correct depth interpolation
-                    // Interpolate 1/z instead of z directly
-                    let one_over_z =
-                        w[0] * (1.0 / v0.z) + w[1] * (1.0 / v1.z) + w[2] * (1.0 / v2.z);
-                    let z_interpolated = 1.0 / one_over_z;
+    // Start from the block containing the top left corner of the bounding box
+    let first_x = min_x / block * block;
+    let first_y = min_y / block * block;
+    let end_x = usize::min((max_x / block + 1) * block, tile.x + tile.width);
+
+    let blocks = (end_x - first_x).div_ceil(block);
+    let block_samples = block * block;
+    if scratch.samples.len() < blocks * block_samples {
+        scratch
+            .samples
+            .resize(blocks * block_samples, Sample::default());
+    }
+    scratch.lengths.clear();
+    scratch.lengths.resize(blocks, 0);
+    let BlockScratch { samples, lengths } = scratch;
 
-                    // Z-buffer test
-                    let buffer_index = y as usize * self.width + x as usize;
-                    if z_interpolated < self.z_buffer[buffer_index] {
-                        // Update z-buffer
-                        self.z_buffer[buffer_index] = z_interpolated;
+    for block_y in (first_y..=max_y).step_by(block) {
+        for y in block_y..usize::min(block_y + block, tile.y + tile.height) {
+            let row = (y - tile.y) * tile.width;
+            let depths = &mut z_buffer[row + first_x - tile.x..row + end_x - tile.x];
+            raster_span(
+                kernel,
+                triangle,
+                y,
+                first_x..end_x,
+                depths,
+                depth_test,
+                |mut sample| {
+                    let x = sample.index;
+                    let b = (x - first_x) / block;
+                    sample.index = row + (x - tile.x);
+                    samples[b * block_samples + lengths[b]] = sample;
+                    lengths[b] += 1;
+                },
+            );
+        }
 
-                        // Update pixel buffer
-                        self.buffer[buffer_index] = color;
+        for (b, length) in lengths.iter_mut().enumerate() {
+            if *length > 0 {
+                let start = b * block_samples;
+                fragment(&samples[start..start + *length]);
+                *length = 0;
+            }
+        }
+    }
+}
+
+// Samples are tested in groups of this many along a row
+const LANES: usize = 8;
+
+// Finds the samples in row y within x_range that are covered and closer than
+// depths, which holds the depth drawn at each sample of the range. The
+// samples found are emitted in order, indexed by their x coordinate, and with
+// DepthTest::Write their depths are written to depths.
+//
+// Weights are worked out in groups of LANES samples, aligned to the start of
+// the row so that a sample gets the same weights however rows are split into
+// tiles. Within a group, every kernel steps them from the group's first
+// sample in the same order of operations to find the same results.
+fn raster_span<E: FnMut(Sample)>(
+    kernel: SpanKernel,
+    triangle: &TriangleSetup,
+    y: usize,
+    x_range: Range<usize>,
+    depths: &mut [f32],
+    depth_test: DepthTest,
+    emit: E,
+) {
+    match kernel {
+        // Spans shorter than a group cost more to set up in vectors than
+        // they save
+        #[cfg(target_arch = "x86_64")]
+        SpanKernel::Avx2 if triangle.lanes_fit && x_range.len() >= LANES => {
+            // Safety: the kernel is only chosen when AVX2 is supported
+            unsafe { raster_span_avx2(triangle, y, x_range, depths, depth_test, emit) };
+        }
+        _ => raster_span_scalar(triangle, y, x_range, depths, depth_test, emit),
+    }
+}
+
+// Edge functions along a row of samples, group by group
+struct SpanSetup {
+    e: [i64; 3],     // edge functions at the first sample of the group
+    steps: [i64; 3], // how much each edge function changes per sample
+}
+
+impl SpanSetup {
+    fn new(triangle: &TriangleSetup, y: usize, x_start: usize) -> SpanSetup {
+        let [edge0, edge1, edge2] = &triangle.edges;
+
+        // Evaluate the edge functions at the center of the first sample
+        let sample_x = ((x_start as i64) << SUBPIXEL_BITS) + SUBPIXEL_HALF;
+        let sample_y = ((y as i64) << SUBPIXEL_BITS) + SUBPIXEL_HALF;
+        let steps = [
+            edge0.a << SUBPIXEL_BITS,
+            edge1.a << SUBPIXEL_BITS,
+            edge2.a << SUBPIXEL_BITS,
+        ];
+
+        SpanSetup {
+            e: [
+                edge0.eval(sample_x, sample_y),
+                edge1.eval(sample_x, sample_y),
+                edge2.eval(sample_x, sample_y),
+            ],
+            steps,
+        }
+    }
+
+    // Barycentric weights w0 and w1 at the first sample of the group: each
+    // vertex is weighted by the edge function of the edge opposite of it
+    fn weights(&self, triangle: &TriangleSetup) -> [f32; 2] {
+        [
+            self.e[1] as f32 * triangle.area_recip,
+            self.e[2] as f32 * triangle.area_recip,
+        ]
+    }
+
+    // How much w0 and w1 change from one sample to the next
+    fn weight_steps(&self, triangle: &TriangleSetup) -> [f32; 2] {
+        [
+            self.steps[1] as f32 * triangle.area_recip,
+            self.steps[2] as f32 * triangle.area_recip,
+        ]
+    }
+
+    // Moves on to the next group of samples
+    fn next_group(&mut self) {
+        for (e, step) in self.e.iter_mut().zip(self.steps.iter()) {
+            *e += step * LANES as i64;
+        }
+    }
+}
+
+fn raster_span_scalar<E: FnMut(Sample)>(
+    triangle: &TriangleSetup,
+    y: usize,
+    x_range: Range<usize>,
+    depths: &mut [f32],
+    depth_test: DepthTest,
+    mut emit: E,
+) {
+    let [edge0, edge1, edge2] = &triangle.edges;
+    let [z0, z1, z2] = triangle.z;
+    let span = SpanSetup::new(triangle, y, x_range.start);
+    let [mut e0, mut e1, mut e2] = span.e;
+    let [step0, step1, step2] = span.steps;
+
+    for x in x_range.clone() {
+        if e0 + edge0.bias >= 0 && e1 + edge1.bias >= 0 && e2 + edge2.bias >= 0 {
+            // Step back to the first sample of the group to find the weights
+            // the same way as every other kernel
+            let lane = (x % LANES) as i64;
+            let group = SpanSetup {
+                e: [e0 - lane * step0, e1 - lane * step1, e2 - lane * step2],
+                ..span
+            };
+            let [w0_start, w1_start] = group.weights(triangle);
+            let [dw0, dw1] = span.weight_steps(triangle);
+            let lane = lane as f32;
+            let w0 = w0_start + lane * dw0;
+            let w1 = w1_start + lane * dw1;
+            let w2 = (1.0 - w0) - w1;
+
+            // Depth in normalized device coordinates (z / w) is an affine
+            // function of screen position, so interpolating it linearly
+            // with the screen space weights is perspective-correct
+            let z = (w0 * z0 + w1 * z1) + w2 * z2;
+
+            let depth = &mut depths[x - x_range.start];
+            if z < *depth {
+                if depth_test == DepthTest::Write {
+                    *depth = z;
+                }
+                emit(Sample {
+                    index: x,
+                    z,
+                    weights: triangle.vertex_weights(w0, w1, w2),
+                });
+            }
+        }
+
+        // Move to the next sample (x+1)
+        e0 += step0;
+        e1 += step1;
+        e2 += step2;
+    }
+}
+
+// Same as raster_span_scalar, but tests a whole group of eight samples at a
+// time. Edge functions are 64 bit, but the offsets of the lanes from the
+// first sample of the group fit in 32 bits for triangles with lanes_fit, so
+// each lane is tested by comparing its offset against a threshold that
+// depends only on the group.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn raster_span_avx2<E: FnMut(Sample)>(
+    triangle: &TriangleSetup,
+    y: usize,
+    x_range: Range<usize>,
+    depths: &mut [f32],
+    depth_test: DepthTest,
+    mut emit: E,
+) {
+    use std::arch::x86_64::*;
+
+    let [edge0, edge1, edge2] = &triangle.edges;
+    let first_group = x_range.start / LANES * LANES;
+    let mut span = SpanSetup::new(triangle, y, first_group);
+
+    let lanes = _mm256_setr_epi32(0, 1, 2, 3, 4, 5, 6, 7);
+    let lanes_f = _mm256_cvtepi32_ps(lanes);
+    let offset0 = _mm256_mullo_epi32(lanes, _mm256_set1_epi32(span.steps[0] as i32));
+    let offset1 = _mm256_mullo_epi32(lanes, _mm256_set1_epi32(span.steps[1] as i32));
+    let offset2 = _mm256_mullo_epi32(lanes, _mm256_set1_epi32(span.steps[2] as i32));
+
+    let [weight_step0, weight_step1] = span.weight_steps(triangle);
+    let dw0 = _mm256_mul_ps(lanes_f, _mm256_set1_ps(weight_step0));
+    let dw1 = _mm256_mul_ps(lanes_f, _mm256_set1_ps(weight_step1));
+    let one = _mm256_set1_ps(1.0);
+    let z0 = _mm256_set1_ps(triangle.z[0]);
+    let z1 = _mm256_set1_ps(triangle.z[1]);
+    let z2 = _mm256_set1_ps(triangle.z[2]);
+
+    let mut z_lanes = [0f32; LANES];
+    let mut w0_lanes = [0f32; LANES];
+    let mut w1_lanes = [0f32; LANES];
+    let mut w2_lanes = [0f32; LANES];
+
+    for group_x in (first_group..x_range.end).step_by(LANES) {
+        // E + k * step + bias >= 0 is k * step > -bias - 1 - E
+        let inside = _mm256_and_si256(
+            _mm256_and_si256(
+                _mm256_cmpgt_epi32(offset0, lane_threshold(span.e[0], edge0.bias)),
+                _mm256_cmpgt_epi32(offset1, lane_threshold(span.e[1], edge1.bias)),
+            ),
+            _mm256_cmpgt_epi32(offset2, lane_threshold(span.e[2], edge2.bias)),
+        );
+
+        // Leave out the lanes outside of the span
+        let begin = _mm256_set1_epi32(x_range.start.saturating_sub(group_x) as i32);
+        let end = _mm256_set1_epi32((x_range.end - group_x).min(LANES) as i32);
+        let in_span = _mm256_andnot_si256(
+            _mm256_cmpgt_epi32(begin, lanes),
+            _mm256_cmpgt_epi32(end, lanes),
+        );
+        let covered = _mm256_and_si256(inside, in_span);
+
+        if _mm256_testz_si256(covered, covered) == 0 {
+            let [w0_start, w1_start] = span.weights(triangle);
+            let w0 = _mm256_add_ps(_mm256_set1_ps(w0_start), dw0);
+            let w1 = _mm256_add_ps(_mm256_set1_ps(w1_start), dw1);
+            let w2 = _mm256_sub_ps(_mm256_sub_ps(one, w0), w1);
+            let z = _mm256_add_ps(
+                _mm256_add_ps(_mm256_mul_ps(w0, z0), _mm256_mul_ps(w1, z1)),
+                _mm256_mul_ps(w2, z2),
+            );
+
+            // Masked loads and stores never touch the lanes left out, so the
+            // group may reach past either end of depths. Its address is only
+            // ever handed to them, hence the wrapping arithmetic.
+            let depth = depths
+                .as_mut_ptr()
+                .wrapping_add(group_x)
+                .wrapping_sub(x_range.start);
+            let old_z = _mm256_maskload_ps(depth, covered);
+            let passed = _mm256_and_ps(
+                _mm256_cmp_ps(z, old_z, _CMP_LT_OQ),
+                _mm256_castsi256_ps(covered),
+            );
+
+            let mut mask = _mm256_movemask_ps(passed) as u32;
+            if mask != 0 {
+                if depth_test == DepthTest::Write {
+                    _mm256_maskstore_ps(depth, _mm256_castps_si256(passed), z);
+                }
+
+                _mm256_storeu_ps(z_lanes.as_mut_ptr(), z);
+                _mm256_storeu_ps(w0_lanes.as_mut_ptr(), w0);
+                _mm256_storeu_ps(w1_lanes.as_mut_ptr(), w1);
+                _mm256_storeu_ps(w2_lanes.as_mut_ptr(), w2);
+
+                while mask != 0 {
+                    let lane = mask.trailing_zeros() as usize;
+                    mask &= mask - 1;
+                    emit(Sample {
+                        index: group_x + lane,
+                        z: z_lanes[lane],
+                        weights: triangle.vertex_weights(
+                            w0_lanes[lane],
+                            w1_lanes[lane],
+                            w2_lanes[lane],
+                        ),
+                    });
+                }
+            }
+        }
+
+        span.next_group();
+    }
+}
+
+// The threshold the lane offsets of an edge must be above, -bias - 1 - e,
+// clamped to 32 bits. Offsets never reach past i32::MAX either way, so a
+// threshold clamped from below still passes every lane, and one clamped from
+// above passes none.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn lane_threshold(e: i64, bias: i64) -> std::arch::x86_64::__m256i {
+    let threshold = (-bias - 1 - e).clamp(i32::MIN as i64, i32::MAX as i64);
+    std::arch::x86_64::_mm256_set1_epi32(threshold as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::projection::ProjectedPoint;
+
+    // Random numbers from a fixed seed, so that every run draws the same
+    // triangles (xorshift64*)
+    struct Random(u64);
+
+    impl Random {
+        // A number in [min, max)
+        fn range(&mut self, min: f32, max: f32) -> f32 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            let unit = (self.0.wrapping_mul(0x2545f4914f6cdd1d) >> 40) as f32 / (1 << 24) as f32;
+            min + (max - min) * unit
+        }
+    }
+
+    // Overlapping triangles up to size across, at random depths, wound
+    // either way, some of them partly off screen
+    fn random_triangles(
+        count: usize,
+        size: f32,
+        width: usize,
+        height: usize,
+        random: &mut Random,
+    ) -> Vec<ProjectedTriangle> {
+        (0..count)
+            .map(|_| {
+                let x = random.range(-size, width as f32 + size);
+                let y = random.range(-size, height as f32 + size);
+                let mut vertex = || ProjectedPoint {
+                    x: x + random.range(-size, size),
+                    y: y + random.range(-size, size),
+                    z: random.range(0.0, 1.0),
+                    w: random.range(0.5, 2.0),
+                };
+                ProjectedTriangle {
+                    v0: vertex(),
+                    v1: vertex(),
+                    v2: vertex(),
+                }
+            })
+            .collect()
+    }
+
+    fn screen(width: usize, height: usize, anti_aliasing: AntiAliasing) -> ScreenBuffer {
+        let mut screen =
+            ScreenBuffer::with_anti_aliasing(width, height, anti_aliasing, ResolveFilter::Box);
+        screen.clear(Rgb::BLACK);
+        screen
+    }
+
+    // Draws triangles with every kind of fill, some opaque and some blended
+    // over them, and returns the samples and their depths
+    fn draw_every_fill(
+        screen: &mut ScreenBuffer,
+        triangles: &[ProjectedTriangle],
+    ) -> (Vec<Rgb>, Vec<f32>) {
+        let quarter = triangles.len() / 4;
+        let colors: Vec<Rgb> = (0..quarter)
+            .map(|i| Rgb::new(i as f32, 0.5, 1.0 / (i + 1) as f32))
+            .collect();
+        let shader = |i: usize, weights: [f32; 3]| Rgb::new(weights[0], weights[1], i as f32);
+
+        screen.fill_projected_triangles(&triangles[..quarter], &colors);
+        screen.fill_projected_triangles_shaded(&triangles[quarter..2 * quarter], shader);
+        screen.fill_projected_triangles_blended(&triangles[2 * quarter..3 * quarter], |i, w| {
+            shader(i, w).with_alpha(0.5)
+        });
+        screen.fill_projected_triangles_weighted(&triangles[3 * quarter..4 * quarter], |i, w| {
+            shader(i, w).with_alpha(0.25)
+        });
+
+        (screen.buffer.clone(), screen.z_buffer.clone())
+    }
+
+    // A closed mesh covering the screen and beyond it: a grid of cells split
+    // into two triangles each, with the grid points moved at random and
+    // snapped to quarter samples so that many samples fall exactly on edges
+    // and vertices. Points move too little for any cell to stop being convex,
+    // so the triangles never overlap. Some triangles are wound the other way.
+    fn grid_mesh(
+        width: usize,
+        height: usize,
+        cell: f32,
+        random: &mut Random,
+    ) -> Vec<ProjectedTriangle> {
+        let columns = (width as f32 / cell) as usize + 4;
+        let rows = (height as f32 / cell) as usize + 4;
+
+        let mut points = Vec::with_capacity((columns + 1) * (rows + 1));
+        for row in 0..=rows {
+            for column in 0..=columns {
+                let jitter = |random: &mut Random| random.range(-0.2, 0.2) * cell;
+                let x = (column as f32 - 2.0) * cell + jitter(random);
+                let y = (row as f32 - 2.0) * cell + jitter(random);
+                let snap = |v: f32| (v * 4.0).round() / 4.0;
+                points.push((snap(x), snap(y)));
+            }
+        }
+
+        let point = |i: usize| ProjectedPoint {
+            x: points[i].0,
+            y: points[i].1,
+            z: 0.5,
+            w: 1.0,
+        };
+
+        let mut triangles = Vec::with_capacity(columns * rows * 2);
+        for row in 0..rows {
+            for column in 0..columns {
+                let corner = |dx: usize, dy: usize| (row + dy) * (columns + 1) + column + dx;
+                let (a, b, c, d) = (corner(0, 0), corner(1, 0), corner(1, 1), corner(0, 1));
+
+                // Alternate the diagonal the cell is split along
+                let halves = if (row + column) % 2 == 0 {
+                    [[a, b, c], [a, c, d]]
+                } else {
+                    [[a, b, d], [b, c, d]]
+                };
+                for [i0, mut i1, mut i2] in halves {
+                    if random.range(0.0, 1.0) < 0.5 {
+                        std::mem::swap(&mut i1, &mut i2);
                     }
+                    triangles.push(ProjectedTriangle {
+                        v0: point(i0),
+                        v1: point(i1),
+                        v2: point(i2),
+                    });
+                }
+            }
+        }
+        triangles
+    }
+
+    #[test]
+    fn closed_mesh_covers_every_sample_once() {
+        let (width, height) = (150, 100);
+
+        let anti_aliasings = [
+            AntiAliasing::None,
+            AntiAliasing::Multisample(4),
+            AntiAliasing::Supersample(2),
+        ];
+
+        for (anti_aliasing, kernel) in anti_aliasings
+            .iter()
+            .flat_map(|&a| kernels().into_iter().map(move |k| (a, k)))
+        {
+            let mut screen = screen(width, height, anti_aliasing);
+            screen.rasterizer.kernel = kernel;
+            let (raster_width, raster_height) = screen.raster_size();
+            let triangles = grid_mesh(raster_width, raster_height, 8.0, &mut Random(3));
+
+            // Count how many triangles cover each sample
+            let block = screen.shading_block();
+            screen.raster_triangles(&triangles, block, DepthTest::Keep, |_, samples, buffer| {
+                for sample in samples {
+                    buffer[sample.index].r += 1.0;
                 }
+            });
+
+            for (i, sample) in screen.buffer.iter().enumerate() {
+                assert!(
+                    sample.r == 1.0,
+                    "sample ({}, {}) covered {} times with {:?} and {:?}",
+                    i % raster_width,
+                    i / raster_width,
+                    sample.r,
+                    anti_aliasing,
+                    kernel
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn tiled_and_untiled_rasterizing_match() {
+        let (width, height) = (300, 200);
+        let triangles = random_triangles(2000, 40.0, width, height, &mut Random(1));
+
+        let rasterizers = [
+            Rasterizer {
+                threads: 1,
+                tiled: false,
+                ..Rasterizer::new()
+            },
+            Rasterizer {
+                threads: 1,
+                tiled: true,
+                ..Rasterizer::new()
+            },
+            Rasterizer {
+                threads: 4,
+                tiled: true,
+                ..Rasterizer::new()
+            },
+        ];
 
-                // Move to the next pixel (x+1)
-                edge_vals[0] += step_x[0];
-                edge_vals[1] += step_x[1];
-                edge_vals[2] += step_x[2];
+        for anti_aliasing in [AntiAliasing::None, AntiAliasing::Multisample(4)] {
+            let drawn: Vec<(Vec<Rgb>, Vec<f32>)> = rasterizers
+                .iter()
+                .map(|&rasterizer| {
+                    let mut screen = screen(width, height, anti_aliasing);
+                    screen.rasterizer = rasterizer;
+                    draw_every_fill(&mut screen, &triangles)
+                })
+                .collect();
+
+            for (other, rasterizer) in drawn[1..].iter().zip(rasterizers[1..].iter()) {
+                assert!(
+                    drawn[0].0 == other.0,
+                    "colors differ with {:?} and {:?}",
+                    rasterizer,
+                    anti_aliasing
+                );
+                assert!(
+                    drawn[0].1 == other.1,
+                    "depths differ with {:?} and {:?}",
+                    rasterizer,
+                    anti_aliasing
+                );
             }
+        }
+    }
+
+    fn kernels() -> Vec<SpanKernel> {
+        let mut kernels = vec![SpanKernel::Scalar];
+        if SpanKernel::detect() != SpanKernel::Scalar {
+            kernels.push(SpanKernel::detect());
+        }
+        kernels
+    }
+
+    #[test]
+    fn span_kernels_match() {
+        let (width, height) = (300, 200);
+        let mut random = Random(4);
+
+        // Small triangles, triangles larger than the screen, and triangles
+        // too large for their lanes to be tested in 32 bits. The first are
+        // drawn twice, in other colors, where the second time must fail the
+        // depth test at every sample.
+        let mut triangles = random_triangles(2000, 40.0, width, height, &mut random);
+        triangles.extend(random_triangles(40, 400.0, width, height, &mut random));
+        triangles.extend(random_triangles(40, 1e5, width, height, &mut random));
+        triangles.splice(100..100, triangles[..100].to_vec());
 
-            // Move to the next row (y+1)
-            row_edge_vals[0] += step_y[0];
-            row_edge_vals[1] += step_y[1];
-            row_edge_vals[2] += step_y[2];
+        for anti_aliasing in [AntiAliasing::None, AntiAliasing::Multisample(4)] {
+            let drawn: Vec<(Vec<Rgb>, Vec<f32>)> = kernels()
+                .into_iter()
+                .map(|kernel| {
+                    let mut screen = screen(width, height, anti_aliasing);
+                    screen.rasterizer.kernel = kernel;
+                    draw_every_fill(&mut screen, &triangles)
+                })
+                .collect();
+
+            for (other, kernel) in drawn[1..].iter().zip(kernels()[1..].iter()) {
+                assert!(
+                    drawn[0].0 == other.0,
+                    "colors differ with {:?} and {:?}",
+                    kernel,
+                    anti_aliasing
+                );
+                assert!(
+                    drawn[0].1 == other.1,
+                    "depths differ with {:?} and {:?}",
+                    kernel,
+                    anti_aliasing
+                );
+            }
         }
     }
 }
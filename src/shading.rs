@@ -0,0 +1,109 @@
+use crate::color::{srgb_to_linear, Rgb};
+use crate::world::Point3;
+
+// Toon bands are spread evenly between these sRGB levels, from the darkest
+// band to the brightest, leaving white for the specular spot
+const TOON_DARK: f32 = 0.35;
+const TOON_LIGHT: f32 = 0.85;
+
+// Surfaces reflecting light towards the viewer at least this closely, as the
+// cosine of the angle between their normal and the halfway vector, are in a
+// toon specular spot
+const TOON_SPECULAR: f32 = 0.97;
+
+pub const DEFAULT_TOON_BANDS: usize = 4;
+
+// How an object's surfaces are shaded
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Shading {
+    // Shades of gray, following the light smoothly
+    #[default]
+    Gray,
+    // Flat bands of gray and a hard white specular spot, like a cartoon
+    Toon {
+        bands: usize,
+    },
+    // Colors of a matcap, by which way surfaces face, regardless of lights
+    Matcap(Matcap),
+}
+
+impl Shading {
+    // Brightness of a surface in linear light, from the light reaching it,
+    // where 1 is full light, and how closely it reflects a light towards the
+    // viewer, which only toon shading uses. Matcaps aren't lit, and pass
+    // light through like gray shading.
+    pub fn shade(&self, light: f32, specular: f32) -> f32 {
+        match *self {
+            Shading::Gray | Shading::Matcap(_) => light,
+            Shading::Toon { bands } => {
+                if specular >= TOON_SPECULAR {
+                    return 1.0;
+                }
+
+                let band = ((light.clamp(0.0, 1.0) * bands as f32) as usize).min(bands - 1);
+                let t = if bands > 1 {
+                    band as f32 / (bands - 1) as f32
+                } else {
+                    1.0
+                };
+                srgb_to_linear(TOON_DARK + (TOON_LIGHT - TOON_DARK) * t)
+            }
+        }
+    }
+
+    pub fn uses_specular(&self) -> bool {
+        matches!(self, Shading::Toon { .. })
+    }
+
+    // Whether surfaces are shaded by normals interpolated from their
+    // vertices, rather than by the flat normal of each face
+    pub fn uses_vertex_normals(&self) -> bool {
+        matches!(self, Shading::Matcap(_))
+    }
+}
+
+// A material captured as a picture of a lit sphere, filling the picture and
+// facing the camera (a material capture). A surface takes the color of the
+// point on the sphere that faces the same way in view space, which shows off
+// its form without any lights.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Matcap {
+    colors: Vec<Rgb>, // in linear light, row by row
+    width: usize,
+    height: usize,
+}
+
+impl Matcap {
+    pub fn load(file_name: &str) -> Result<Matcap, String> {
+        let image = match image::open(file_name) {
+            Ok(image) => image.to_rgb8(),
+            Err(e) => return Err(e.to_string()),
+        };
+        if image.width() == 0 || image.height() == 0 {
+            return Err("empty image".to_string());
+        }
+
+        let colors = image
+            .pixels()
+            .map(|p| {
+                Rgb::new(p[0] as f32, p[1] as f32, p[2] as f32).map(|c| srgb_to_linear(c / 255.0))
+            })
+            .collect();
+
+        Ok(Matcap {
+            colors,
+            width: image.width() as usize,
+            height: image.height() as usize,
+        })
+    }
+
+    // Color for a unit normal in view space. The normal's x and y are where
+    // it points on the sphere, as seen from the camera.
+    pub fn color(&self, normal: Point3) -> Rgb {
+        let u = (0.5 + normal[0] * 0.5) * self.width as f32;
+        let v = (0.5 - normal[1] * 0.5) * self.height as f32;
+        let x = (u.max(0.0) as usize).min(self.width - 1);
+        let y = (v.max(0.0) as usize).min(self.height - 1);
+        self.colors[y * self.width + x]
+    }
+}
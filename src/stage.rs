@@ -0,0 +1,111 @@
+use crate::lighting::{Lighting, LightingOptions};
+use crate::render::{
+    render_object, render_surfaces, ObjectOrientation, RenderMode, RenderState, TransformedObject,
+};
+use crate::scene::Renderer;
+use crate::screen_buffer::ScreenBuffer;
+use crate::world::camera::Camera;
+use crate::world::light::Light;
+use crate::world::{Object, Point3};
+
+// The ground plane reaches this many times the object's radius out from
+// underneath its center
+const GROUND_EXTENT: f32 = 4.0;
+
+// An object together with the lights shining on it, and optionally a ground
+// plane underneath it to catch its shadows
+pub struct Stage {
+    object: Object,
+    radius: f32, // distance of the farthest vertex from the object's origin
+    lights: Vec<Light>,
+    options: LightingOptions,
+}
+
+impl Stage {
+    pub fn new(object: Object, lights: Vec<Light>, options: LightingOptions) -> Stage {
+        let radius = object
+            .vertices()
+            .iter()
+            .map(|v| v.magnitude())
+            .fold(0.0, f32::max);
+
+        Stage {
+            object,
+            radius,
+            lights,
+            options,
+        }
+    }
+
+    // A sphere containing the object in world space. It only depends on the
+    // object's position and scale, so that shadows and the ground plane stay
+    // put as the object rotates.
+    fn bounding_sphere(&self, orientation: &ObjectOrientation) -> (Point3, f32) {
+        let (sx, sy, sz) = orientation.scale;
+        let scale = sx.abs().max(sy.abs()).max(sz.abs());
+        (orientation.position, self.radius * scale)
+    }
+
+    // A square facing up, just underneath the bounding sphere
+    fn ground_plane(center: Point3, radius: f32) -> Object {
+        let height = center[1] - radius;
+        let extent = radius * GROUND_EXTENT;
+        let corner = |dx: f32, dz: f32| {
+            Point3::new([center[0] + dx * extent, height, center[2] + dz * extent])
+        };
+
+        Object::new(
+            vec![
+                corner(-1.0, -1.0),
+                corner(-1.0, 1.0),
+                corner(1.0, 1.0),
+                corner(1.0, -1.0),
+            ],
+            vec![vec![0, 1, 2, 3]],
+        )
+    }
+}
+
+impl Renderer<RenderState> for Stage {
+    fn render(&self, screen: &mut ScreenBuffer, camera: &Camera, state: RenderState) {
+        // Lighting only applies to surfaces, which the line modes don't show
+        let shows_surfaces = matches!(
+            state.options.mode,
+            RenderMode::Solid | RenderMode::SolidWireframe
+        );
+        if !shows_surfaces {
+            render_object(&self.object, screen, camera, state, None);
+            return;
+        }
+
+        let (center, radius) = self.bounding_sphere(&state.orientation);
+        let lighting = if self.lights.is_empty() {
+            None
+        } else {
+            Some(Lighting::new(
+                &self.lights,
+                &self.object,
+                state.orientation,
+                center,
+                radius,
+                &self.options,
+            ))
+        };
+
+        if self.options.ground_plane {
+            let ground = Stage::ground_plane(center, radius);
+            let transformed = TransformedObject::new(&ground, camera, ObjectOrientation::default());
+            render_surfaces(
+                &ground,
+                &transformed,
+                screen,
+                camera,
+                false,
+                lighting.as_ref(),
+                &state.options,
+            );
+        }
+
+        render_object(&self.object, screen, camera, state, lighting.as_ref());
+    }
+}
@@ -1,15 +1,35 @@
+use core::f32;
+
 use crate::matrix::Matrix;
 use crate::world::three_dim::make_rotation_matrix;
-use crate::world::{Point2, Point3};
+use crate::world::{Point3, Point4};
+
+// Default lens: a 90 degree vertical field of view
+pub const DEFAULT_FOV_Y: f32 = f32::consts::FRAC_PI_2;
+pub const DEFAULT_NEAR: f32 = 0.1;
+pub const DEFAULT_FAR: f32 = 100.0;
+pub const DEFAULT_VIEW_HEIGHT: f32 = 8.0;
 
-use super::projection::ProjectedPoint;
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Projection {
+    Perspective,
+    Orthographic,
+}
 
 pub struct Camera {
     position: Point3,
     rotation: (f32, f32, f32),
+
+    projection: Projection,
+    fov_y: f32,       // vertical field of view, in radians, for perspective projection
+    view_height: f32, // height of the visible region, in world units, for orthographic projection
+    near: f32,
+    far: f32,
+    aspect_ratio: f32,
+
     view_matrix: Matrix<4, 4>,
-    focal_matrix: Matrix<3, 4>,
-    combined_matrix: Matrix<3, 4>,
+    projection_matrix: Matrix<4, 4>,
+    combined_matrix: Matrix<4, 4>,
     modified: bool,
 }
 
@@ -17,14 +37,21 @@ impl Camera {
     pub fn new(position: Point3, aspect_ratio: f32) -> Camera {
         let rotation = (0.0, 0.0, 0.0);
         let view_matrix = rotation_view_matrix(position, rotation);
-        let focal_matrix = make_focal_matrix(0.0, 0.0, aspect_ratio);
+        let projection_matrix =
+            make_perspective_matrix(DEFAULT_FOV_Y, aspect_ratio, DEFAULT_NEAR, DEFAULT_FAR);
 
         Camera {
             position,
             rotation,
+            projection: Projection::Perspective,
+            fov_y: DEFAULT_FOV_Y,
+            view_height: DEFAULT_VIEW_HEIGHT,
+            near: DEFAULT_NEAR,
+            far: DEFAULT_FAR,
+            aspect_ratio,
             view_matrix,
-            focal_matrix,
-            combined_matrix: focal_matrix * view_matrix,
+            projection_matrix,
+            combined_matrix: projection_matrix * view_matrix,
             modified: false,
         }
     }
@@ -37,6 +64,39 @@ impl Camera {
         self.rotation
     }
 
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+        self.update_projection();
+    }
+
+    /// Sets the vertical field of view, in radians. Must be within (0, pi).
+    pub fn set_fov_y(&mut self, fov_y: f32) {
+        debug_assert!(fov_y > 0.0 && fov_y < f32::consts::PI);
+        self.fov_y = fov_y;
+        self.update_projection();
+    }
+
+    /// Sets the height of the region visible with orthographic projection, in
+    /// world units. Must be positive.
+    pub fn set_view_height(&mut self, view_height: f32) {
+        debug_assert!(view_height > 0.0);
+        self.view_height = view_height;
+        self.update_projection();
+    }
+
+    /// Sets the distances of the near and far clipping planes. Must satisfy
+    /// 0 < near < far.
+    pub fn set_clip_planes(&mut self, near: f32, far: f32) {
+        debug_assert!(0.0 < near && near < far);
+        self.near = near;
+        self.far = far;
+        self.update_projection();
+    }
+
     pub fn move_to(&mut self, point: Point3) {
         self.position = point;
     }
@@ -46,29 +106,110 @@ impl Camera {
     }
 
     pub fn point_to(&mut self, point: Point3) {
-        self.view_matrix = point_to_view_matrix(self.position, point, Y_AXIS);
+        // Keep world up pointing up, unless looking straight up or down
+        let forward = (point - self.position).normalize();
+        let up = if forward.dot(Y_AXIS).abs() > 0.999 {
+            Z_AXIS
+        } else {
+            Y_AXIS
+        };
+
+        self.view_matrix = point_to_view_matrix(self.position, point, up);
+        self.combined_matrix = self.projection_matrix * self.view_matrix;
+        self.modified = true;
     }
 
     pub fn update(&mut self) {
         self.view_matrix = rotation_view_matrix(self.position, self.rotation);
-        self.combined_matrix = self.focal_matrix * self.view_matrix;
+        self.combined_matrix = self.projection_matrix * self.view_matrix;
         self.modified = true;
     }
 
-    pub fn project_point(&self, p: Point3) -> Point2 {
-        (self.combined_matrix * p.euc_to_hom()).hom_to_euc()
+    fn update_projection(&mut self) {
+        self.projection_matrix = match self.projection {
+            Projection::Perspective => {
+                make_perspective_matrix(self.fov_y, self.aspect_ratio, self.near, self.far)
+            }
+            Projection::Orthographic => {
+                make_orthographic_matrix(self.view_height, self.aspect_ratio, self.near, self.far)
+            }
+        };
+        self.combined_matrix = self.projection_matrix * self.view_matrix;
+        self.modified = true;
+    }
+
+    // Matrix taking world space to homogeneous clip space
+    pub fn view_projection_matrix(&self) -> Matrix<4, 4> {
+        self.combined_matrix
+    }
+
+    // Transforms a point into homogeneous clip space, where visible points
+    // satisfy -w <= x, y <= w and 0 <= z <= w
+    pub fn project_to_clip(&self, p: Point3) -> Point4 {
+        self.combined_matrix * p.euc_to_hom()
+    }
+
+    // Takes depth normalized to [0, 1] between the near and far planes back
+    // to view space depth, undoing the projection's depth mapping
+    pub fn view_depth(&self, z: f32) -> f32 {
+        match self.projection {
+            Projection::Perspective => {
+                self.near * self.far / (self.far - z * (self.far - self.near))
+            }
+            Projection::Orthographic => self.near + z * (self.far - self.near),
+        }
+    }
+
+    // Length on screen, in pixels, of something size world units long at the
+    // given view space depth, on a screen screen_height pixels tall
+    pub fn projected_size(&self, size: f32, depth: f32, screen_height: usize) -> f32 {
+        let visible_height = match self.projection {
+            Projection::Perspective => 2.0 * depth * (self.fov_y / 2.0).tan(),
+            Projection::Orthographic => self.view_height,
+        };
+        size / visible_height * screen_height as f32
     }
 
-    pub fn project_point_with_depth(&self, p: Point3) -> ProjectedPoint {
-        let proj = self.project_point(p);
-        let dist_squared = (p - self.position).magnitude_2();
-        ProjectedPoint {
-            x: proj[0],
-            y: proj[1],
-            z: dist_squared,
+    // Returns the direction of the viewing ray that passes through p. Rays
+    // diverge from the camera position with perspective projection, but are
+    // all parallel to the camera's forward axis with orthographic projection.
+    pub fn view_direction(&self, p: Point3) -> Point3 {
+        match self.projection {
+            Projection::Perspective => (p - self.position).normalize(),
+            Projection::Orthographic => self.forward(),
         }
     }
 
+    // View space depth of a point in world space, its distance in front of
+    // the camera along the forward axis
+    pub fn depth_of(&self, p: Point3) -> f32 {
+        (p - self.position).dot(self.forward())
+    }
+
+    // Rotates a direction in world space into view space, where x points
+    // right, y up, and z forward away from the camera
+    pub fn direction_to_view(&self, d: Point3) -> Point3 {
+        Point3::new([
+            d.dot(self.axis(0)),
+            d.dot(self.axis(1)),
+            d.dot(self.axis(2)),
+        ])
+    }
+
+    fn forward(&self) -> Point3 {
+        self.axis(2)
+    }
+
+    // The camera's right, up, or forward axis in world space, which are the
+    // rows of the view matrix's rotation
+    fn axis(&self, row: usize) -> Point3 {
+        Point3::new([
+            self.view_matrix[(0, row)],
+            self.view_matrix[(1, row)],
+            self.view_matrix[(2, row)],
+        ])
+    }
+
     pub fn get_and_clear_modified(&mut self) -> bool {
         if self.modified {
             self.modified = false;
@@ -114,10 +255,39 @@ fn axes_transformation_matrix(
     ])
 }
 
-fn make_focal_matrix(cam_x: f32, cam_y: f32, aspect_ratio: f32) -> Matrix<3, 4> {
+// Perspective projection for a camera looking down +z. View space depth in
+// [near, far] is mapped to [0, 1], and w takes the view space depth for the
+// perspective divide.
+//
+// ref: https://learn.microsoft.com/en-us/windows/win32/direct3d9/projection-transform
+fn make_perspective_matrix(fov_y: f32, aspect_ratio: f32, near: f32, far: f32) -> Matrix<4, 4> {
+    let focal_length = (fov_y / 2.0).tan().recip();
+    let depth_scale = far / (far - near);
+
     Matrix::new([
-        [aspect_ratio.recip(), 0.0, 0.0, -cam_x],
-        [0.0, 1.0, 0.0, -cam_y],
+        [focal_length / aspect_ratio, 0.0, 0.0, 0.0],
+        [0.0, focal_length, 0.0, 0.0],
+        [0.0, 0.0, depth_scale, -near * depth_scale],
         [0.0, 0.0, 1.0, 0.0],
     ])
 }
+
+// Orthographic projection for a camera looking down +z. A region view_height
+// tall is mapped onto [-1, 1] vertically, view space depth in [near, far] is
+// mapped to [0, 1], and w is always 1.
+fn make_orthographic_matrix(
+    view_height: f32,
+    aspect_ratio: f32,
+    near: f32,
+    far: f32,
+) -> Matrix<4, 4> {
+    let scale = 2.0 / view_height;
+    let depth_scale = (far - near).recip();
+
+    Matrix::new([
+        [scale / aspect_ratio, 0.0, 0.0, 0.0],
+        [0.0, scale, 0.0, 0.0],
+        [0.0, 0.0, depth_scale, -near * depth_scale],
+        [0.0, 0.0, 0.0, 1.0],
+    ])
+}
@@ -0,0 +1,178 @@
+use core::f32;
+
+use crate::world::camera::{Camera, Projection};
+use crate::world::Point3;
+
+// Brightness of a light when none is given
+pub const DEFAULT_INTENSITY: f32 = 0.7;
+// Cone angle of a spot light when none is given, in radians
+pub const DEFAULT_SPOT_ANGLE: f32 = f32::consts::FRAC_PI_3;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum LightKind {
+    // Parallel rays travelling in a direction, like sunlight
+    Directional {
+        direction: Point3,
+    },
+    // Rays spreading from a position into a cone around a direction. The
+    // angle is the full width of the cone, in radians.
+    Spot {
+        position: Point3,
+        direction: Point3,
+        angle: f32,
+    },
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct Light {
+    pub kind: LightKind,
+    pub intensity: f32,
+}
+
+impl Light {
+    pub fn directional(direction: Point3) -> Light {
+        Light {
+            kind: LightKind::Directional {
+                direction: direction.normalize(),
+            },
+            intensity: DEFAULT_INTENSITY,
+        }
+    }
+
+    /// Creates a spot light at position shining towards target. The cone
+    /// angle is in radians and must be within (0, pi).
+    pub fn spot(position: Point3, target: Point3, angle: f32) -> Light {
+        debug_assert!(angle > 0.0 && angle < f32::consts::PI);
+        Light {
+            kind: LightKind::Spot {
+                position,
+                direction: (target - position).normalize(),
+                angle,
+            },
+            intensity: DEFAULT_INTENSITY,
+        }
+    }
+
+    // Unit vector pointing from p towards the light
+    pub fn direction_to_light(&self, p: Point3) -> Point3 {
+        match self.kind {
+            LightKind::Directional { direction } => -direction,
+            LightKind::Spot { position, .. } => (position - p).normalize(),
+        }
+    }
+
+    // How much of the light reaches p through the light's cone, fading out
+    // towards the edge of a spot light's cone
+    pub fn cone_factor(&self, p: Point3) -> f32 {
+        match self.kind {
+            LightKind::Directional { .. } => 1.0,
+            LightKind::Spot {
+                position,
+                direction,
+                angle,
+            } => {
+                let cos_angle = (p - position).normalize().dot(direction);
+                let outer = (angle / 2.0).cos();
+                let inner = (angle / 2.0 * SPOT_INNER_FRACTION).cos();
+                smoothstep(outer, inner, cos_angle)
+            }
+        }
+    }
+
+    // Camera looking from the light at a sphere, framing it as tightly as
+    // possible. Everything that can cast a shadow must be inside the sphere.
+    pub fn shadow_camera(&self, center: Point3, radius: f32) -> Camera {
+        // An object that is a single point still needs a view with some depth
+        let radius = radius.max(MIN_SHADOW_RADIUS);
+        match self.kind {
+            LightKind::Directional { direction } => {
+                // Back away from the sphere far enough to see all of it
+                let mut camera = Camera::new(center - direction * (2.0 * radius), 1.0);
+                camera.set_projection(Projection::Orthographic);
+                camera.set_view_height(2.0 * radius);
+                camera.set_clip_planes(0.5 * radius, 3.5 * radius);
+                camera.point_to(center);
+                camera
+            }
+            LightKind::Spot {
+                position,
+                direction,
+                angle,
+            } => {
+                let distance = (center - position).magnitude();
+                let near = (distance - radius).max(MIN_SHADOW_NEAR);
+                let far = (distance + radius).max(near * 2.0);
+
+                let mut camera = Camera::new(position, 1.0);
+                camera.set_fov_y(angle);
+                camera.set_clip_planes(near, far);
+                camera.point_to(position + direction);
+                camera
+            }
+        }
+    }
+}
+
+// Fraction of a spot light's cone that is fully lit, before fading out
+const SPOT_INNER_FRACTION: f32 = 0.8;
+// Closest a shadow camera's near plane may be to the light
+const MIN_SHADOW_NEAR: f32 = 0.05;
+// Smallest sphere a shadow camera frames, keeping its clip planes apart
+const MIN_SHADOW_RADIUS: f32 = 1e-3;
+
+// Eases from 0 at edge0 to 1 at edge1
+//
+// ref: https://en.wikipedia.org/wiki/Smoothstep
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Normalized depth of p as seen by camera
+    fn depth(camera: &Camera, p: Point3) -> f32 {
+        let clip = camera.project_to_clip(p);
+        clip[2] / clip[3]
+    }
+
+    #[test]
+    fn shadow_camera_frames_zero_radius_objects() {
+        let center = Point3::new([1.0, 2.0, 3.0]);
+        let lights = [
+            Light::directional(Point3::new([0.0, -1.0, 0.0])),
+            Light::spot(Point3::new([1.0, 5.0, 3.0]), center, DEFAULT_SPOT_ANGLE),
+        ];
+
+        for light in &lights {
+            let z = depth(&light.shadow_camera(center, 0.0), center);
+            assert!(z > 0.0 && z < 1.0, "center at depth {}", z);
+        }
+    }
+
+    #[test]
+    fn cone_factor_fades_out_at_cone_edge() {
+        let position = Point3::default();
+        let light = Light::spot(
+            position,
+            Point3::new([0.0, 0.0, 1.0]),
+            f32::consts::FRAC_PI_2,
+        );
+
+        // A point at a given angle from the cone's axis, in degrees
+        let at_angle = |degrees: f32| {
+            let (sin, cos) = degrees.to_radians().sin_cos();
+            Point3::new([sin, 0.0, cos])
+        };
+
+        assert_eq!(light.cone_factor(at_angle(0.0)), 1.0);
+        assert_eq!(light.cone_factor(at_angle(30.0)), 1.0);
+        assert!(light.cone_factor(at_angle(45.0)) < 1e-4);
+        assert_eq!(light.cone_factor(at_angle(60.0)), 0.0);
+
+        let inner = light.cone_factor(at_angle(40.0));
+        assert!(inner > 0.0 && inner < 1.0, "{} between the edges", inner);
+    }
+}
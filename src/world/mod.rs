@@ -1,8 +1,8 @@
 pub mod camera;
 pub mod geo;
+pub mod light;
 pub mod projection;
 pub mod three_dim;
 
-pub use geo::{Point, Point2, Point3};
-pub use projection::projection_to_screen;
+pub use geo::{Point, Point3, Point4};
 pub use three_dim::Object;
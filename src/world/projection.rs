@@ -1,10 +1,17 @@
-use crate::world::Point2;
+use crate::world::{Point3, Point4};
 
 #[derive(Clone, Debug)]
 pub struct ProjectedPoint {
     pub x: f32, // screen x
     pub y: f32, // screen y
-    pub z: f32, // depth
+    pub z: f32, // depth, normalized to [0, 1] between the near and far planes
+    pub w: f32, // clip space w, for perspective-correct interpolation
+}
+
+impl ProjectedPoint {
+    pub fn pixel(&self) -> (isize, isize) {
+        (self.x.floor() as isize, self.y.floor() as isize)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -14,50 +21,259 @@ pub struct ProjectedTriangle {
     pub v2: ProjectedPoint,
 }
 
-fn projection_to_ndc(p: Point2, width: usize, height: usize) -> Point2 {
-    Point2::new([
-        (p[0] + (width as f32 / 2.0)) / (width as f32),
-        (p[1] + (height as f32 / 2.0)) / (height as f32),
-    ])
+// Polygons are also clipped to a guard band this many times the size of the
+// screen, which keeps their screen coordinates small enough for fixed point
+// rasterization
+const GUARD_BAND: f32 = 8.0;
+
+// Signed distances from the clipping planes in clip space, which are
+// non-negative on the visible side of each plane
+fn near_plane_distance(p: &Point4) -> f32 {
+    p[2]
 }
 
-fn ndc_to_screen(ndc: Point2, screen_size: (usize, usize)) -> (isize, isize) {
-    (
-        (ndc[0] * screen_size.0 as f32).floor() as isize,
-        ((1.0 - ndc[1]) * screen_size.1 as f32).floor() as isize,
-    )
+fn far_plane_distance(p: &Point4) -> f32 {
+    p[3] - p[2]
 }
 
-pub fn projection_to_screen(
-    p: Point2,
-    proj_size: (usize, usize),
-    screen_size: (usize, usize),
-) -> (isize, isize) {
-    let ndc = projection_to_ndc(p, proj_size.0, proj_size.1); // move to normalized device coordinates
-    ndc_to_screen(ndc, screen_size) // move to screen coordinates
+fn left_guard_distance(p: &Point4) -> f32 {
+    GUARD_BAND * p[3] + p[0]
 }
 
-fn projected_point_to_ndc(p: ProjectedPoint, width: usize, height: usize) -> ProjectedPoint {
-    ProjectedPoint {
-        x: (p.x + (width as f32 / 2.0)) / (width as f32),
-        y: (p.y + (height as f32 / 2.0)) / (height as f32),
-        z: p.z,
+fn right_guard_distance(p: &Point4) -> f32 {
+    GUARD_BAND * p[3] - p[0]
+}
+
+fn bottom_guard_distance(p: &Point4) -> f32 {
+    GUARD_BAND * p[3] + p[1]
+}
+
+fn top_guard_distance(p: &Point4) -> f32 {
+    GUARD_BAND * p[3] - p[1]
+}
+
+const DEPTH_PLANES: [fn(&Point4) -> f32; 2] = [near_plane_distance, far_plane_distance];
+
+const POLYGON_PLANES: [fn(&Point4) -> f32; 6] = [
+    near_plane_distance,
+    far_plane_distance,
+    left_guard_distance,
+    right_guard_distance,
+    bottom_guard_distance,
+    top_guard_distance,
+];
+
+pub fn in_depth_range(p: &Point4) -> bool {
+    DEPTH_PLANES.iter().all(|plane| plane(p) >= 0.0)
+}
+
+// Finds where the segment a-->b crosses a plane, given the signed distance of
+// each endpoint from the plane. Clip space is linear, so this is a plain lerp.
+//
+// a must be the endpoint on the visible side. Computing the intersection from
+// the same endpoint whichever way an edge is traversed means that polygons
+// sharing the edge get exactly the same new vertex, leaving no cracks.
+fn plane_intersection(a: Point4, b: Point4, dist_a: f32, dist_b: f32) -> Point4 {
+    let t = dist_a / (dist_a - dist_b);
+    a + (b - a) * t
+}
+
+// Clips a convex polygon in clip space against the near and far planes, as
+// well as the guard band. The result is empty when the polygon is entirely
+// outside of the depth range.
+//
+// ref: https://en.wikipedia.org/wiki/Sutherland%E2%80%93Hodgman_algorithm
+pub fn clip_polygon(polygon: &[Point4]) -> Vec<Point4> {
+    clip_vertices(polygon.to_vec(), |p| *p, plane_intersection)
+}
+
+// Clips a polygon like clip_polygon, along with a normal at each of its
+// vertices. New vertices get normals interpolated between the vertices of the
+// edge they are on.
+pub fn clip_polygon_with_normals(
+    polygon: &[Point4],
+    normals: &[Point3],
+) -> (Vec<Point4>, Vec<Point3>) {
+    let vertices = polygon
+        .iter()
+        .copied()
+        .zip(normals.iter().copied())
+        .collect();
+    let clipped = clip_vertices(
+        vertices,
+        |&(p, _)| p,
+        |(a, n_a), (b, n_b), dist_a, dist_b| {
+            let t = dist_a / (dist_a - dist_b);
+            (
+                plane_intersection(a, b, dist_a, dist_b),
+                n_a + (n_b - n_a) * t,
+            )
+        },
+    );
+    clipped.into_iter().unzip()
+}
+
+// Sutherland-Hodgman over POLYGON_PLANES, for vertices that have a position
+// in clip space, and a way to find where an edge between two of them crosses
+// a plane
+fn clip_vertices<V: Copy>(
+    polygon: Vec<V>,
+    position: fn(&V) -> Point4,
+    intersection: fn(V, V, f32, f32) -> V,
+) -> Vec<V> {
+    let mut res = polygon;
+
+    for plane in POLYGON_PLANES.iter() {
+        if res.is_empty() {
+            break;
+        }
+
+        let input = std::mem::take(&mut res);
+        let mut prev = input[input.len() - 1];
+        let mut prev_dist = plane(&position(&prev));
+
+        for &curr in input.iter() {
+            let curr_dist = plane(&position(&curr));
+
+            if curr_dist >= 0.0 {
+                if prev_dist < 0.0 {
+                    // entering the visible side
+                    res.push(intersection(curr, prev, curr_dist, prev_dist));
+                }
+                res.push(curr);
+            } else if prev_dist >= 0.0 {
+                // leaving the visible side
+                res.push(intersection(prev, curr, prev_dist, curr_dist));
+            }
+
+            prev = curr;
+            prev_dist = curr_dist;
+        }
     }
+
+    res
+}
+
+// Clips a line segment in clip space against the near and far planes
+pub fn clip_line(mut a: Point4, mut b: Point4) -> Option<(Point4, Point4)> {
+    for plane in DEPTH_PLANES.iter() {
+        let dist_a = plane(&a);
+        let dist_b = plane(&b);
+
+        if dist_a < 0.0 && dist_b < 0.0 {
+            return None;
+        } else if dist_a < 0.0 {
+            a = plane_intersection(b, a, dist_b, dist_a);
+        } else if dist_b < 0.0 {
+            b = plane_intersection(a, b, dist_a, dist_b);
+        }
+    }
+
+    Some((a, b))
 }
 
-fn ndc_point_to_screen(ndc: ProjectedPoint, screen_size: (usize, usize)) -> ProjectedPoint {
+// Performs the perspective divide on a clipped point and maps it onto the
+// screen. Screen space places (0, 0) at the top left corner, while normalized
+// device coordinates span [-1, 1] with +y pointing up.
+pub fn clip_to_screen(p: Point4, screen_size: (usize, usize)) -> ProjectedPoint {
+    let ndc = p.hom_to_euc();
     ProjectedPoint {
-        x: (ndc.x * screen_size.0 as f32),
-        y: ((1.0 - ndc.y) * screen_size.1 as f32),
-        z: ndc.z,
+        x: (ndc[0] + 1.0) / 2.0 * screen_size.0 as f32,
+        y: (1.0 - ndc[1]) / 2.0 * screen_size.1 as f32,
+        z: ndc[2],
+        w: p[3],
     }
 }
 
-pub fn projected_point_to_screen(
-    p: ProjectedPoint,
-    proj_size: (usize, usize),
-    screen_size: (usize, usize),
-) -> ProjectedPoint {
-    let ndc = projected_point_to_ndc(p, proj_size.0, proj_size.1); // move to normalized device coordinates
-    ndc_point_to_screen(ndc, screen_size) // move to screen coordinates
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::Point;
+
+    fn assert_near<const D: usize>(points: &[Point<D>], expected: &[[f32; D]]) {
+        let coords: Vec<[f32; D]> = points.iter().map(|p| p.coords()).collect();
+        assert_eq!(
+            coords.len(),
+            expected.len(),
+            "{:?} is not {:?}",
+            coords,
+            expected
+        );
+        for (a, b) in coords.iter().zip(expected.iter()) {
+            assert!(
+                a.iter().zip(b.iter()).all(|(a, b)| (a - b).abs() < 1e-5),
+                "{:?} is not {:?}",
+                coords,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn clip_polygon_cuts_at_near_plane_and_guard_band() {
+        let polygon = [
+            Point4::new([0.0, 0.0, -1.0, 1.0]),
+            Point4::new([24.0, 0.0, 1.0, 1.0]),
+            Point4::new([0.0, 4.0, 1.0, 1.0]),
+        ];
+
+        // The near plane cuts off the first vertex, then the right edge of
+        // the guard band, at x = 8 w, cuts off the second
+        assert_near(
+            &clip_polygon(&polygon),
+            &[
+                [0.0, 2.0, 0.0, 1.0],
+                [8.0, 2.0 / 3.0, 0.0, 1.0],
+                [8.0, 8.0 / 3.0, 1.0, 1.0],
+                [0.0, 4.0, 1.0, 1.0],
+            ],
+        );
+
+        let behind = [
+            Point4::new([0.0, 0.0, -1.0, 1.0]),
+            Point4::new([1.0, 0.0, -2.0, 1.0]),
+            Point4::new([0.0, 1.0, -1.0, 1.0]),
+        ];
+        assert!(clip_polygon(&behind).is_empty());
+    }
+
+    #[test]
+    fn clipping_interpolates_normals_along_cut_edges() {
+        let polygon = [
+            Point4::new([0.0, 0.0, -1.0, 1.0]),
+            Point4::new([0.0, 0.0, 1.0, 1.0]),
+            Point4::new([1.0, 0.0, 1.0, 1.0]),
+        ];
+        let normals = [
+            Point3::new([1.0, 0.0, 0.0]),
+            Point3::new([0.0, 1.0, 0.0]),
+            Point3::new([0.0, 0.0, 1.0]),
+        ];
+        let (clipped, clipped_normals) = clip_polygon_with_normals(&polygon, &normals);
+
+        // The new vertices are halfway along the edges crossing the near
+        // plane, with normals halfway between those of the edges' ends
+        assert_near(
+            &clipped,
+            &[
+                [0.5, 0.0, 0.0, 1.0],
+                [0.0, 0.0, 0.0, 1.0],
+                [0.0, 0.0, 1.0, 1.0],
+                [1.0, 0.0, 1.0, 1.0],
+            ],
+        );
+        assert_near(
+            &clipped_normals,
+            &[
+                [0.5, 0.0, 0.5],
+                [0.5, 0.5, 0.0],
+                [0.0, 1.0, 0.0],
+                [0.0, 0.0, 1.0],
+            ],
+        );
+
+        // Positions are clipped exactly like they are without normals
+        let without: Vec<[f32; 4]> = clip_polygon(&polygon).iter().map(|p| p.coords()).collect();
+        assert_near(&clipped, &without);
+    }
 }
@@ -1,55 +1,44 @@
-use std::{fmt, ops};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 
 use crate::matrix::Matrix;
+use crate::shading::Shading;
 use crate::world::Point3;
 
-pub struct Face {
-    vertices: Vec<Point3>,
-}
-
-impl Face {
-    fn new(vertices: Vec<Point3>) -> Face {
-        Face { vertices }
-    }
-
-    pub fn vertices(&self) -> &Vec<Point3> {
-        &self.vertices
-    }
-}
-
-impl ops::Index<usize> for Face {
-    type Output = Point3;
-
-    fn index(&self, index: usize) -> &Self::Output {
-        &self.vertices[index]
-    }
-}
-
-impl ops::IndexMut<usize> for Face {
-    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
-        &mut self.vertices[index]
-    }
-}
-
 pub struct Object {
     size: (f32, f32, f32),
 
     vertices: Vec<Point3>,
-    faces: Vec<Face>,
     face_indexes: Vec<Vec<usize>>,
+    face_normals: Vec<Point3>,
+    vertex_normals: Vec<Point3>, // smoothed across the faces sharing each vertex
+    face_opacities: Vec<f32>,    // in [0, 1], where 1 is opaque
+    edges: Vec<(usize, usize)>,
+    edge_faces: Vec<Vec<usize>>, // indexes of the faces sharing each edge
+
+    shading: Shading,
 }
 
 // todo: consider returning references throughout program
 impl Object {
     pub fn new(vertices: Vec<Point3>, face_indexes: Vec<Vec<usize>>) -> Object {
         let size = compute_size(&vertices);
-        let faces = map_faces(&face_indexes, &vertices);
+        let face_normals = compute_face_normals(&face_indexes, &vertices);
+        let vertex_normals = compute_vertex_normals(&face_indexes, &face_normals, &vertices);
+        let face_opacities = vec![1.0; face_indexes.len()];
+        let edges = compute_edges(&face_indexes);
+        let edge_faces = compute_edge_faces(&face_indexes, &edges);
 
         Object {
             size,
             vertices,
-            faces,
             face_indexes,
+            face_normals,
+            vertex_normals,
+            face_opacities,
+            edges,
+            edge_faces,
+            shading: Shading::default(),
         }
     }
 
@@ -72,11 +61,52 @@ impl Object {
         });
 
         self.size = compute_size(&self.vertices);
-        self.faces = map_faces(&self.face_indexes, &self.vertices);
+        self.face_normals = compute_face_normals(&self.face_indexes, &self.vertices);
+        self.vertex_normals =
+            compute_vertex_normals(&self.face_indexes, &self.face_normals, &self.vertices);
+    }
+
+    pub fn face_indexes(&self) -> &Vec<Vec<usize>> {
+        &self.face_indexes
+    }
+
+    pub fn face_normals(&self) -> &Vec<Point3> {
+        &self.face_normals
+    }
+
+    pub fn vertex_normals(&self) -> &Vec<Point3> {
+        &self.vertex_normals
     }
 
-    pub fn faces(&self) -> &Vec<Face> {
-        &self.faces
+    pub fn face_opacities(&self) -> &Vec<f32> {
+        &self.face_opacities
+    }
+
+    pub fn set_face_opacities(&mut self, opacities: Vec<f32>) {
+        assert_eq!(opacities.len(), self.face_indexes.len());
+        self.face_opacities = opacities;
+    }
+
+    // Makes the whole object more transparent, scaling the opacity of every
+    // face by opacity
+    pub fn fade(&mut self, opacity: f32) {
+        self.face_opacities.iter_mut().for_each(|o| *o *= opacity);
+    }
+
+    pub fn edges(&self) -> &Vec<(usize, usize)> {
+        &self.edges
+    }
+
+    pub fn edge_faces(&self) -> &Vec<Vec<usize>> {
+        &self.edge_faces
+    }
+
+    pub fn shading(&self) -> &Shading {
+        &self.shading
+    }
+
+    pub fn set_shading(&mut self, shading: Shading) {
+        self.shading = shading;
     }
 }
 
@@ -123,13 +153,104 @@ pub fn compute_center(vertices: &[Point3]) -> Point3 {
     extremes.0.midpoint(extremes.1)
 }
 
-pub fn map_faces(face_indexes: &[Vec<usize>], vertices: &[Point3]) -> Vec<Face> {
+// Computes the unit normal of each face from its first three vertices, with
+// the winding of the vertices deciding which way the normal points
+pub fn compute_face_normals(face_indexes: &[Vec<usize>], vertices: &[Point3]) -> Vec<Point3> {
     face_indexes
         .iter()
-        .map(|si| Face::new(si.iter().map(|&n| vertices[n]).collect()))
+        .map(|face| {
+            let a = vertices[face[0]];
+            let ab = vertices[face[1]] - a;
+            let ac = vertices[face[2]] - a;
+            ab.cross(ac).normalize()
+        })
+        .collect()
+}
+
+// Computes a unit normal at each vertex by averaging the normals of the faces
+// sharing it, so that shading can curve smoothly across faces. Each face is
+// weighted by its angle at the vertex, which keeps how a flat side is split
+// into faces from tilting the normal towards it.
+//
+// Degenerate faces, which have no normal, are left out. Vertices that no face
+// uses, or whose faces cancel out, are left with a zero normal.
+//
+// ref: https://en.wikipedia.org/wiki/Vertex_normal
+pub fn compute_vertex_normals(
+    face_indexes: &[Vec<usize>],
+    face_normals: &[Point3],
+    vertices: &[Point3],
+) -> Vec<Point3> {
+    let mut normals = vec![Point3::default(); vertices.len()];
+    for (face, &normal) in face_indexes.iter().zip(face_normals.iter()) {
+        if !normal.magnitude().is_finite() {
+            continue;
+        }
+        for (k, &i) in face.iter().enumerate() {
+            let prev = vertices[face[(k + face.len() - 1) % face.len()]] - vertices[i];
+            let next = vertices[face[(k + 1) % face.len()]] - vertices[i];
+            let cos = prev.dot(next) / (prev.magnitude() * next.magnitude());
+            if cos.is_finite() {
+                normals[i] = normals[i] + normal * cos.clamp(-1.0, 1.0).acos();
+            }
+        }
+    }
+
+    normals
+        .iter()
+        .map(|&n| {
+            if n.magnitude() > 0.0 {
+                n.normalize()
+            } else {
+                n
+            }
+        })
         .collect()
 }
 
+// Collects the unique edges of all faces as pairs of vertex indexes, ordered
+// so that an edge shared by two faces is only listed once
+pub fn compute_edges(face_indexes: &[Vec<usize>]) -> Vec<(usize, usize)> {
+    let mut seen: HashSet<(usize, usize)> = HashSet::new();
+    let mut edges: Vec<(usize, usize)> = Vec::new();
+
+    for face in face_indexes {
+        for (i, &a) in face.iter().enumerate() {
+            let b = face[(i + 1) % face.len()];
+            let edge = (usize::min(a, b), usize::max(a, b));
+            if seen.insert(edge) {
+                edges.push(edge);
+            }
+        }
+    }
+
+    edges
+}
+
+// Finds the faces sharing each edge. Edges inside of a closed surface have
+// two faces, while edges on the border of an open surface have one.
+pub fn compute_edge_faces(
+    face_indexes: &[Vec<usize>],
+    edges: &[(usize, usize)],
+) -> Vec<Vec<usize>> {
+    let edge_indexes: HashMap<(usize, usize), usize> = edges
+        .iter()
+        .enumerate()
+        .map(|(i, &edge)| (edge, i))
+        .collect();
+    let mut edge_faces: Vec<Vec<usize>> = vec![Vec::new(); edges.len()];
+
+    for (f, face) in face_indexes.iter().enumerate() {
+        for (i, &a) in face.iter().enumerate() {
+            let b = face[(i + 1) % face.len()];
+            let edge = (usize::min(a, b), usize::max(a, b));
+            edge_faces[edge_indexes[&edge]].push(f);
+        }
+    }
+
+    edge_faces
+}
+
 pub fn make_rotation_matrix(rx: f32, ry: f32, rz: f32) -> Matrix<3, 3> {
     // aliases
     let sin = f32::sin;
@@ -151,6 +272,36 @@ pub fn make_rotation_matrix(rx: f32, ry: f32, rz: f32) -> Matrix<3, 3> {
     ])
 }
 
+// Builds the matrix taking object space to world space: scaled along each
+// axis, then rotated about the origin, then moved to position
+pub fn make_model_matrix(
+    position: Point3,
+    rotation: (f32, f32, f32),
+    scale: (f32, f32, f32),
+) -> Matrix<4, 4> {
+    let rot_matrix = make_rotation_matrix(rotation.0, rotation.1, rotation.2);
+    let row = |i: usize| {
+        let r = rot_matrix.row(i).unwrap();
+        [r[0] * scale.0, r[1] * scale.1, r[2] * scale.2, position[i]]
+    };
+
+    Matrix::new([row(0), row(1), row(2), [0.0, 0.0, 0.0, 1.0]])
+}
+
+// Builds the matrix taking object space normals to world space. Normals must
+// be transformed by the inverse transpose of the model matrix to stay
+// perpendicular to their faces under non-uniform scale, which for a rotation
+// R and scale S is (R * S)^-T = R * S^-1.
+pub fn make_normal_matrix(rotation: (f32, f32, f32), scale: (f32, f32, f32)) -> Matrix<3, 3> {
+    let rot_matrix = make_rotation_matrix(rotation.0, rotation.1, rotation.2);
+    let row = |i: usize| {
+        let r = rot_matrix.row(i).unwrap();
+        [r[0] / scale.0, r[1] / scale.1, r[2] / scale.2]
+    };
+
+    Matrix::new([row(0), row(1), row(2)])
+}
+
 pub fn rotate_point_with_matrix(p: Point3, center: Point3, rot_matrix: &Matrix<3, 3>) -> Point3 {
     // 1. Translate p so that center is now at origin
     let mut n = p - center;
@@ -160,10 +311,60 @@ pub fn rotate_point_with_matrix(p: Point3, center: Point3, rot_matrix: &Matrix<3
     n + center
 }
 
-pub fn rotate_point_about_origin_with_matrix(p: Point3, rot_matrix: &Matrix<3, 3>) -> Point3 {
-    *rot_matrix * p
-}
-
 pub fn rotate_point(p: Point3, center: Point3, rot: (f32, f32, f32)) -> Point3 {
     rotate_point_with_matrix(p, center, &make_rotation_matrix(rot.0, rot.1, rot.2))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A cube of side 2 around the origin, with vertex i at -1 or 1 on each
+    // axis by its bits, and faces wound counterclockwise from outside
+    fn cube() -> (Vec<Point3>, Vec<Vec<usize>>) {
+        let sign = |i: usize, bit: usize| if i & bit == 0 { -1.0 } else { 1.0 };
+        let vertices = (0..8)
+            .map(|i| Point3::new([sign(i, 1), sign(i, 2), sign(i, 4)]))
+            .collect();
+        let faces = vec![
+            vec![0, 4, 6, 2],
+            vec![1, 3, 7, 5],
+            vec![0, 1, 5, 4],
+            vec![2, 6, 7, 3],
+            vec![0, 2, 3, 1],
+            vec![4, 5, 7, 6],
+        ];
+        (vertices, faces)
+    }
+
+    fn assert_diagonal_normals(object: &Object) {
+        for (vertex, normal) in object.vertices.iter().zip(object.vertex_normals()) {
+            let diagonal = vertex.normalize();
+            assert!(
+                (*normal - diagonal).magnitude() < 1e-5,
+                "normal at {} is {}, not {}",
+                vertex,
+                normal,
+                diagonal
+            );
+        }
+    }
+
+    #[test]
+    fn cube_vertex_normals_point_along_diagonals() {
+        let (vertices, faces) = cube();
+        assert_diagonal_normals(&Object::new(vertices, faces));
+    }
+
+    #[test]
+    fn splitting_faces_keeps_vertex_normals() {
+        // Splitting each side into two triangles gives some corners one face
+        // of a side and others two, which weighting by angle makes up for
+        let (vertices, faces) = cube();
+        let triangles = faces
+            .iter()
+            .flat_map(|f| vec![vec![f[0], f[1], f[2]], vec![f[0], f[2], f[3]]])
+            .collect();
+        assert_diagonal_normals(&Object::new(vertices, triangles));
+    }
+}